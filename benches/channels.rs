@@ -0,0 +1,69 @@
+// Run with: cargo bench --bench channels
+// Same number of producers and consumers, every message goes through the channel once
+use learn_concurrency_rust::channel::{mpmc, ring_buffer, Channel};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const PAIR_COUNTS: [usize; 4] = [1, 2, 4, 8];
+const TOTAL_MSGS: usize = 400_000;
+const CAPACITY: usize = 64;
+
+// send and recv are called TOTAL_MSGS / pairs times per producer/consumer thread
+fn run(pairs: usize, send: impl Fn(usize) + Sync, recv: impl Fn() -> usize + Sync) -> Duration {
+    let msgs_per_thread = TOTAL_MSGS / pairs;
+    let start = Instant::now();
+    let sum: usize = thread::scope(|s| {
+        for _ in 0..pairs {
+            s.spawn(|| {
+                for i in 0..msgs_per_thread {
+                    send(i);
+                }
+            });
+        }
+        let consumers: Vec<_> = (0..pairs)
+            .map(|_| s.spawn(|| (0..msgs_per_thread).map(|_| recv()).sum::<usize>()))
+            .collect();
+        consumers.into_iter().map(|t| t.join().unwrap()).sum()
+    });
+    let elapsed = start.elapsed();
+    assert_eq!(sum, pairs * msgs_per_thread * (msgs_per_thread - 1) / 2);
+    elapsed
+}
+
+fn report(name: &str, pairs: usize, elapsed: Duration) {
+    let ns_per_msg = elapsed.as_nanos() as f64 / TOTAL_MSGS as f64;
+    println!(
+        "{name:<12} pairs: {pairs:>2}  total: {elapsed:>12.3?}  per msg: {ns_per_msg:>8.1} ns"
+    );
+}
+
+fn main() {
+    for pairs in PAIR_COUNTS {
+        // Unbounded, std Mutex + Condvar
+        let channel = Channel::new();
+        let elapsed = run(
+            pairs,
+            |i| channel.send(i).unwrap(),
+            || channel.receive().unwrap(),
+        );
+        report("mutex", pairs, elapsed);
+
+        let (sender, receiver) = mpmc::bounded(CAPACITY);
+        let elapsed = run(
+            pairs,
+            |i| sender.send(i).unwrap(),
+            || receiver.recv().unwrap(),
+        );
+        report("mpmc", pairs, elapsed);
+
+        let (sender, receiver) = ring_buffer::bounded(CAPACITY);
+        let elapsed = run(
+            pairs,
+            |i| sender.send(i).unwrap(),
+            || receiver.recv().unwrap(),
+        );
+        report("ring_buffer", pairs, elapsed);
+
+        println!();
+    }
+}
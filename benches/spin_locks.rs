@@ -0,0 +1,61 @@
+// Run with: cargo bench --bench spin_locks
+// Every thread increments a counter behind the lock, so the lock is always contended
+use learn_concurrency_rust::sync::{ClhLock, McsLock, SpinLock, TicketLock, TtasSpinLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const THREAD_COUNTS: [usize; 4] = [2, 4, 8, 16];
+const TOTAL_OPS: usize = 400_000;
+
+fn run<L: Sync>(lock: &L, threads: usize, increment: impl Fn(&L) + Sync) -> Duration {
+    let ops_per_thread = TOTAL_OPS / threads;
+    let start = Instant::now();
+    thread::scope(|s| {
+        for _ in 0..threads {
+            s.spawn(|| {
+                for _ in 0..ops_per_thread {
+                    increment(lock);
+                }
+            });
+        }
+    });
+    start.elapsed()
+}
+
+fn report(name: &str, threads: usize, elapsed: Duration) {
+    let ns_per_op = elapsed.as_nanos() as f64 / TOTAL_OPS as f64;
+    println!(
+        "{name:<12} threads: {threads:>2}  total: {elapsed:>12.3?}  per op: {ns_per_op:>8.1} ns"
+    );
+}
+
+fn main() {
+    for threads in THREAD_COUNTS {
+        let lock = SpinLock::new(0usize);
+        let elapsed = run(&lock, threads, |l| *l.lock() += 1);
+        assert_eq!(lock.into_inner(), TOTAL_OPS / threads * threads);
+        report("swap", threads, elapsed);
+
+        let lock = TtasSpinLock::new(0usize);
+        let elapsed = run(&lock, threads, |l| *l.lock() += 1);
+        assert_eq!(lock.into_inner(), TOTAL_OPS / threads * threads);
+        report("ttas", threads, elapsed);
+
+        let lock = TicketLock::new(0usize);
+        let elapsed = run(&lock, threads, |l| *l.lock() += 1);
+        assert_eq!(lock.into_inner(), TOTAL_OPS / threads * threads);
+        report("ticket", threads, elapsed);
+
+        let lock = McsLock::new(0usize);
+        let elapsed = run(&lock, threads, |l| *l.lock() += 1);
+        assert_eq!(lock.into_inner(), TOTAL_OPS / threads * threads);
+        report("mcs", threads, elapsed);
+
+        let lock = ClhLock::new(0usize);
+        let elapsed = run(&lock, threads, |l| *l.lock() += 1);
+        assert_eq!(lock.into_inner(), TOTAL_OPS / threads * threads);
+        report("clh", threads, elapsed);
+
+        println!();
+    }
+}
@@ -0,0 +1,57 @@
+// Public surface of the channels built in chapter 5
+// Chapters stay as they are for learning, this module only re-exports the usable versions
+
+pub use crate::chapter5_build_channels::error::{
+    BroadcastRecvError, BroadcastTryRecvError, RecvError, RecvTimeoutError, SendError, TryRecvError,
+};
+pub use crate::chapter5_build_channels::priority_version::PriorityChannel;
+pub use crate::chapter5_build_channels::rendezvous_version::RendezvousChannel;
+pub use crate::chapter5_build_channels::select::Select;
+pub use crate::chapter5_build_channels::simple_version::Channel;
+pub use crate::chapter5_build_channels::stats::ChannelStats;
+pub use mpmc::{bounded, unbounded};
+
+pub mod broadcast {
+    pub use crate::chapter5_build_channels::broadcast_version::{channel, Receiver, Sender};
+}
+
+pub mod mpmc {
+    pub use crate::chapter5_build_channels::mpmc_version::{
+        bounded, bounded_with_stats, unbounded, unbounded_with_stats, IntoIter, Iter, Receiver,
+        RecvFuture, SendFuture, Sender, TryIter,
+    };
+}
+
+pub mod ring_buffer {
+    pub use crate::chapter5_build_channels::ring_buffer_version::{bounded, Receiver, Sender};
+}
+
+pub mod spsc {
+    pub use crate::chapter5_build_channels::spsc_version::{bounded, Consumer, Producer};
+}
+
+pub mod oneshot {
+    pub use crate::chapter5_build_channels::arc_version::{channel, Receiver, Sender};
+    // Borrowed halves, no allocation but only usable inside thread::scope
+    pub use crate::chapter5_build_channels::type_safe_version::{
+        Channel, Receiver as BorrowedReceiver, Sender as BorrowedSender,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_share_between_spawned_threads() {
+        let channel = Arc::new(Channel::default());
+        let t = thread::spawn({
+            let channel = channel.clone();
+            move || channel.send("hello")
+        });
+        assert_eq!(channel.receive(), Ok("hello"));
+        t.join().unwrap().unwrap();
+    }
+}
@@ -0,0 +1,218 @@
+use crate::chapter4_build_spin_lock::node_cache::{self, NodeCache};
+use crate::chapter4_build_spin_lock::ttas_version::Backoff;
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::ptr;
+use std::sync::atomic::Ordering::{AcqRel, Acquire, Release};
+use std::sync::atomic::{AtomicBool, AtomicPtr};
+
+// CLH queue lock (Craig, Landin and Hagersten)
+// Like MCS, but the queue is implicit: each waiter spins on the node of its predecessor
+// Unlock is a single store to our own node, no need to wait for a successor to show up
+// The catch is the node outlives our guard: the successor takes it over once it has the lock
+// and uses it for its next lock, so nodes move between threads instead of being allocated each time
+// That also means there is no try_lock, once in the queue a thread can't back out
+
+struct Node {
+    // True while the owner of this node holds or waits for the lock
+    locked: AtomicBool,
+}
+
+// Node this thread uses for its next lock: the predecessor's node from its last unlock
+thread_local! {
+    static NODE_CACHE: NodeCache<Node> = const { NodeCache::new() };
+}
+
+impl Node {
+    fn acquire() -> *mut Node {
+        node_cache::acquire(&NODE_CACHE, || Node {
+            locked: AtomicBool::new(true),
+        })
+    }
+
+    fn release(node: *mut Node) {
+        node_cache::release(&NODE_CACHE, node);
+    }
+}
+
+pub struct ClhLock<T> {
+    // Node of the last thread that queued up, null if nobody ever did
+    tail: AtomicPtr<Node>,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for ClhLock<T> {}
+
+impl<T> ClhLock<T> {
+    pub const fn new(val: T) -> ClhLock<T> {
+        ClhLock {
+            tail: AtomicPtr::new(ptr::null_mut()),
+            data: UnsafeCell::new(val),
+        }
+    }
+
+    pub fn lock(&self) -> Guard<'_, T> {
+        let node = Node::acquire();
+        let pred = self.tail.swap(node, AcqRel);
+        // First one ever has no predecessor
+        if !pred.is_null() {
+            let mut step = 0;
+            while unsafe { (*pred).locked.load(Acquire) } {
+                Backoff::default().snooze(&mut step);
+            }
+        }
+        Guard {
+            inner: self,
+            node,
+            pred,
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        // Moving data out means Drop never runs, so free the tail node here
+        let mut this = std::mem::ManuallyDrop::new(self);
+        unsafe {
+            this.free_tail();
+            ptr::read(&this.data).into_inner()
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    // No guard is alive, so the tail is the last released node and nobody else will free it
+    fn free_tail(&mut self) {
+        let tail = *self.tail.get_mut();
+        if !tail.is_null() {
+            drop(unsafe { Box::from_raw(tail) });
+        }
+    }
+}
+
+impl<T: Default> Default for ClhLock<T> {
+    fn default() -> ClhLock<T> {
+        ClhLock::new(T::default())
+    }
+}
+
+impl<T> fmt::Debug for ClhLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Can't look into the tail node, its successor might free it at any time
+        f.debug_struct("ClhLock").finish_non_exhaustive()
+    }
+}
+
+impl<T> Drop for ClhLock<T> {
+    fn drop(&mut self) {
+        self.free_tail();
+    }
+}
+
+pub struct Guard<'a, T> {
+    inner: &'a ClhLock<T>,
+    node: *mut Node,
+    // Released node of the previous holder, nobody else looks at it anymore
+    // Null if there was no previous holder
+    pred: *mut Node,
+}
+
+unsafe impl<T: Sync> Sync for Guard<'_, T> {}
+
+impl<T> Deref for Guard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.inner.data.get() }
+    }
+}
+
+impl<T> DerefMut for Guard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.inner.data.get() }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Guard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T> Drop for Guard<'_, T> {
+    fn drop(&mut self) {
+        if !self.pred.is_null() {
+            Node::release(self.pred);
+        }
+        // After this store our node belongs to the successor (or stays as tail)
+        unsafe { (*self.node).locked.store(false, Release) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chapter4_build_spin_lock::tests::{check_guard, check_mutual_exclusion};
+    use std::sync::atomic::Ordering::Relaxed;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test() {
+        let nums = ClhLock::new(Vec::new());
+        check_guard(|| nums.lock());
+    }
+
+    #[test]
+    fn test_mutual_exclusion() {
+        let counter = ClhLock::new(0u64);
+        check_mutual_exclusion(|| counter.lock());
+    }
+
+    #[test]
+    fn test_fifo() {
+        const THREADS: u32 = 8;
+
+        let order = ClhLock::new(Vec::new());
+        let guard = order.lock();
+
+        thread::scope(|s| {
+            for id in 0..THREADS {
+                let order = &order;
+                let tail = order.tail.load(Relaxed);
+                s.spawn(move || order.lock().push(id));
+                // Wait until this thread joined the queue before starting the next one
+                while order.tail.load(Relaxed) == tail {
+                    thread::sleep(Duration::from_millis(1));
+                }
+            }
+            drop(guard);
+        });
+
+        assert_eq!(order.into_inner(), (0..THREADS).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_reuses_node() {
+        let lock = ClhLock::new(0);
+        let first = lock.lock().node;
+        // Takes over the first node as its predecessor, then keeps it for the next lock
+        drop(lock.lock());
+        assert_eq!(lock.lock().node, first);
+
+        // Holding two at once needs a second node, both go back without leaking
+        let other = ClhLock::new(0);
+        let (a, b) = (lock.lock(), other.lock());
+        assert_ne!(a.node, b.node);
+        drop((a, b));
+        *lock.lock() += 1;
+        assert_eq!(other.into_inner(), 0);
+    }
+
+    #[test]
+    fn test_const_new() {
+        static LOCK: ClhLock<u32> = ClhLock::new(0);
+        *LOCK.lock() += 1;
+        assert_eq!(*LOCK.lock(), 1);
+    }
+}
@@ -0,0 +1,242 @@
+use crate::chapter4_build_spin_lock::node_cache::{self, NodeCache};
+use crate::chapter4_build_spin_lock::ttas_version::Backoff;
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::ptr;
+use std::sync::atomic::Ordering::{AcqRel, Acquire, Relaxed, Release};
+use std::sync::atomic::{AtomicBool, AtomicPtr};
+
+// MCS queue lock (Mellor-Crummey and Scott)
+// Waiters form a linked list, each one spins on the flag in its own node
+// Unlock only writes to the successor's node, so waiters don't fight over one shared cache line
+// like they do with the flag in safe_version::SpinLock
+
+struct Node {
+    locked: AtomicBool,
+    next: AtomicPtr<Node>,
+}
+
+thread_local! {
+    static NODE_CACHE: NodeCache<Node> = const { NodeCache::new() };
+}
+
+impl Node {
+    fn acquire() -> *mut Node {
+        node_cache::acquire(&NODE_CACHE, || Node {
+            locked: AtomicBool::new(true),
+            next: AtomicPtr::new(ptr::null_mut()),
+        })
+    }
+
+    fn release(node: *mut Node) {
+        node_cache::release(&NODE_CACHE, node);
+    }
+}
+
+pub struct McsLock<T> {
+    // Last node in the queue, null when unlocked
+    tail: AtomicPtr<Node>,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for McsLock<T> {}
+
+impl<T> McsLock<T> {
+    pub const fn new(val: T) -> McsLock<T> {
+        McsLock {
+            tail: AtomicPtr::new(ptr::null_mut()),
+            data: UnsafeCell::new(val),
+        }
+    }
+
+    pub fn lock(&self) -> Guard<'_, T> {
+        // Node is owned by the guard, other threads only reach it through the queue
+        let node = Node::acquire();
+
+        // Release publishes our node, Acquire gets the predecessor's node
+        let prev = self.tail.swap(node, AcqRel);
+        if !prev.is_null() {
+            unsafe {
+                // Predecessor can't free its node before it sees us in next
+                (*prev).next.store(node, Release);
+                let mut step = 0;
+                while (*node).locked.load(Acquire) {
+                    Backoff::default().snooze(&mut step);
+                }
+            }
+        }
+
+        Guard { inner: self, node }
+    }
+
+    pub fn try_lock(&self) -> Option<Guard<'_, T>> {
+        let node = Node::acquire();
+        // Only join the queue if it's empty
+        match self
+            .tail
+            .compare_exchange(ptr::null_mut(), node, AcqRel, Relaxed)
+        {
+            Ok(_) => Some(Guard { inner: self, node }),
+            Err(_) => {
+                Node::release(node);
+                None
+            }
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+}
+
+impl<T: Default> Default for McsLock<T> {
+    fn default() -> McsLock<T> {
+        McsLock::new(T::default())
+    }
+}
+
+impl<T> fmt::Debug for McsLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("McsLock")
+            .field("locked", &!self.tail.load(Relaxed).is_null())
+            .finish_non_exhaustive()
+    }
+}
+
+pub struct Guard<'a, T> {
+    inner: &'a McsLock<T>,
+    node: *mut Node,
+}
+
+unsafe impl<T: Sync> Sync for Guard<'_, T> {}
+
+impl<T> Deref for Guard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.inner.data.get() }
+    }
+}
+
+impl<T> DerefMut for Guard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.inner.data.get() }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Guard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T> Drop for Guard<'_, T> {
+    fn drop(&mut self) {
+        unsafe {
+            let mut next = (*self.node).next.load(Acquire);
+            if next.is_null() {
+                // No successor yet, try to mark the lock as free
+                if self
+                    .inner
+                    .tail
+                    .compare_exchange(self.node, ptr::null_mut(), Release, Relaxed)
+                    .is_ok()
+                {
+                    Node::release(self.node);
+                    return;
+                }
+                // A successor already swapped itself into tail, wait until it links to our node
+                let mut step = 0;
+                loop {
+                    next = (*self.node).next.load(Acquire);
+                    if !next.is_null() {
+                        break;
+                    }
+                    Backoff::default().snooze(&mut step);
+                }
+            }
+            // Hand the lock over, after this the successor owns the lock and we never touch it again
+            (*next).locked.store(false, Release);
+            Node::release(self.node);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chapter4_build_spin_lock::tests::{check_guard, check_mutual_exclusion};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test() {
+        let nums = McsLock::new(Vec::new());
+        check_guard(|| nums.lock());
+    }
+
+    #[test]
+    fn test_mutual_exclusion() {
+        let counter = McsLock::new(0u64);
+        check_mutual_exclusion(|| counter.lock());
+    }
+
+    #[test]
+    fn test_fifo() {
+        const THREADS: u32 = 8;
+
+        let order = McsLock::new(Vec::new());
+        let guard = order.lock();
+
+        thread::scope(|s| {
+            for id in 0..THREADS {
+                let order = &order;
+                let tail = order.tail.load(Relaxed);
+                s.spawn(move || order.lock().push(id));
+                // Wait until this thread joined the queue before starting the next one
+                while order.tail.load(Relaxed) == tail {
+                    thread::sleep(Duration::from_millis(1));
+                }
+            }
+            assert!(order.try_lock().is_none());
+            drop(guard);
+        });
+
+        assert_eq!(order.into_inner(), (0..THREADS).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_try_lock() {
+        let lock = McsLock::new(0);
+
+        let mut guard = lock.try_lock().unwrap();
+        *guard += 1;
+        assert!(lock.try_lock().is_none());
+        drop(guard);
+
+        *lock.lock() += 1;
+        assert_eq!(*lock.try_lock().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_reuses_node() {
+        let lock = McsLock::new(0);
+        let guard = lock.lock();
+        let node = guard.node;
+        drop(guard);
+        assert_eq!(lock.lock().node, node);
+        assert_eq!(lock.try_lock().unwrap().node, node);
+
+        // Holding two at once needs a second node, both go back without leaking
+        let other = McsLock::new(0);
+        let (a, b) = (lock.lock(), other.lock());
+        assert_ne!(a.node, b.node);
+        drop((a, b));
+        *lock.lock() += 1;
+        *other.lock() += 1;
+    }
+}
@@ -1,18 +1,23 @@
-mod safe_version;
+pub(crate) mod clh_version;
+pub(crate) mod mcs_version;
+mod node_cache;
+pub(crate) mod safe_version;
+pub(crate) mod ticket_version;
+pub(crate) mod ttas_version;
 mod unsafe_version;
 
 #[cfg(test)]
-mod tests {
-    use std::sync::atomic::AtomicBool;
-    use std::sync::atomic::Ordering::{Acquire, Release};
+pub(crate) mod tests {
+    use std::ops::DerefMut;
+    use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+    use std::sync::atomic::{AtomicBool, AtomicUsize};
     use std::thread;
-    use std::time::Duration;
 
     #[test]
     fn test_minimal() {
         struct SpinLock {
             lock: AtomicBool,
-        };
+        }
 
         impl SpinLock {
             pub const fn new() -> SpinLock {
@@ -22,8 +27,9 @@ mod tests {
             }
 
             pub fn lock(&self) {
-                // If swap atomic operator return false, mean it is locking
-                while !self.lock.swap(true, Acquire) {
+                // swap returns the previous value, true means another thread is holding the lock
+                // Keep spinning until we are the one who changed it from false to true
+                while self.lock.swap(true, Acquire) {
                     std::hint::spin_loop();
                 }
             }
@@ -44,18 +50,72 @@ mod tests {
         static LOCK: SpinLock = SpinLock::new();
         static mut DATA: [u32; 10] = [0; 10];
 
-        for i in 0..10 {
-            thread::spawn(move || {
-                LOCK.lock();
-                unsafe { DATA[i] = generate_data() };
-                LOCK.unlock();
-            });
+        let threads: Vec<_> = (0..10)
+            .map(|i| {
+                thread::spawn(move || {
+                    LOCK.lock();
+                    unsafe { DATA[i] = generate_data() };
+                    LOCK.unlock();
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
         }
 
-        thread::sleep(Duration::from_millis(100));
+        // generate_data is only called under the lock, so every thread gets a unique number
+        let mut data = unsafe { DATA };
+        data.sort();
+        assert_eq!(data, std::array::from_fn(|i| i as u32 + 1));
+    }
 
-        for i in unsafe { &DATA } {
-            println!("DATA: {}", *i);
+    // Non-atomic read-modify-write, with a gap between read and write to widen the race window
+    // If two threads are ever inside the lock at the same time, some increments get lost
+    pub(crate) fn racy_increment(value: &mut u64) {
+        let read = std::hint::black_box(*value);
+        for _ in 0..10 {
+            std::hint::spin_loop();
         }
+        *value = read + 1;
+    }
+
+    // Checks shared by every lock in this chapter, lock locks the lock under test,
+    // e.g. check_guard(|| nums.lock())
+    // One thread pushes once, the other twice while holding the lock: its two pushes stay together
+    pub(crate) fn check_guard<G: DerefMut<Target = Vec<i32>>>(lock: impl Fn() -> G + Sync) {
+        thread::scope(|s| {
+            s.spawn(|| lock().push(1));
+            s.spawn(|| {
+                let mut guard = lock();
+                guard.push(2);
+                guard.push(2);
+            });
+        });
+
+        let guard = lock();
+        assert!(*guard == [1, 2, 2] || *guard == [2, 2, 1]);
+    }
+
+    // Counter behind the lock has to start at 0
+    pub(crate) fn check_mutual_exclusion<G: DerefMut<Target = u64>>(lock: impl Fn() -> G + Sync) {
+        const THREADS: u64 = 8;
+        const ITERATIONS: u64 = 2000;
+
+        // Number of threads inside the critical section, must never be more than one
+        let inside = AtomicUsize::new(0);
+        thread::scope(|s| {
+            for _ in 0..THREADS {
+                s.spawn(|| {
+                    for _ in 0..ITERATIONS {
+                        let mut guard = lock();
+                        assert_eq!(inside.fetch_add(1, Relaxed), 0);
+                        racy_increment(&mut guard);
+                        inside.fetch_sub(1, Relaxed);
+                    }
+                });
+            }
+        });
+
+        assert_eq!(*lock(), THREADS * ITERATIONS);
     }
 }
@@ -0,0 +1,50 @@
+use std::cell::Cell;
+use std::ptr;
+use std::thread::LocalKey;
+
+// Queue nodes of McsLock and ClhLock, kept per thread so taking the lock doesn't go through malloc
+// every time
+// Each lock declares its own thread local, the node types are different:
+//     thread_local! { static NODE_CACHE: NodeCache<Node> = const { NodeCache::new() }; }
+// A thread holding several locks at once needs more nodes, the extra ones are allocated and freed
+pub(crate) struct NodeCache<N>(Cell<*mut N>);
+
+impl<N> NodeCache<N> {
+    pub(crate) const fn new() -> NodeCache<N> {
+        NodeCache(Cell::new(ptr::null_mut()))
+    }
+}
+
+impl<N> Drop for NodeCache<N> {
+    fn drop(&mut self) {
+        let node = self.0.get();
+        if !node.is_null() {
+            drop(unsafe { Box::from_raw(node) });
+        }
+    }
+}
+
+// Cached node if there is one, reset to what fresh returns, or a new allocation
+// Writing the reused node without atomics is fine, nobody else can reach it until the caller
+// publishes it (the swap into the lock's tail)
+pub(crate) fn acquire<N>(cache: &'static LocalKey<NodeCache<N>>, fresh: fn() -> N) -> *mut N {
+    // try_with: the thread local is gone if a guard is dropped by another thread local's destructor
+    let node = cache
+        .try_with(|cache| cache.0.replace(ptr::null_mut()))
+        .unwrap_or(ptr::null_mut());
+    if node.is_null() {
+        return Box::into_raw(Box::new(fresh()));
+    }
+    unsafe { node.write(fresh()) };
+    node
+}
+
+// No other thread can reach the node anymore
+pub(crate) fn release<N>(cache: &'static LocalKey<NodeCache<N>>, node: *mut N) {
+    let old = cache
+        .try_with(|cache| cache.0.replace(node))
+        .unwrap_or(node);
+    if !old.is_null() {
+        drop(unsafe { Box::from_raw(old) });
+    }
+}
@@ -1,15 +1,18 @@
 use std::cell::UnsafeCell;
+use std::fmt;
 use std::ops::{Deref, DerefMut};
 use std::sync::atomic::AtomicBool;
-use std::sync::atomic::Ordering::{Acquire, Release};
+use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+use std::time::{Duration, Instant};
 
-struct SpinLock<T> {
+pub struct SpinLock<T> {
     lock: AtomicBool,
     data: UnsafeCell<T>,
 }
 
 // Allow SpinLock to be shared between threads
-unsafe impl<T> Sync for SpinLock<T> {}
+// Only the thread holding the lock can touch data, so T only need to be Send (like std Mutex)
+unsafe impl<T: Send> Sync for SpinLock<T> {}
 
 impl<T> SpinLock<T> {
     pub const fn new(val: T) -> SpinLock<T> {
@@ -19,19 +22,82 @@ impl<T> SpinLock<T> {
         }
     }
 
-    pub fn lock(&self) -> Guard<T> {
-        while !self.lock.swap(true, Acquire) {
+    pub fn lock(&self) -> Guard<'_, T> {
+        while self.lock.swap(true, Acquire) {
             std::hint::spin_loop();
         }
 
         Guard { inner: self }
     }
+
+    // Attempt the lock only once, never spin
+    pub fn try_lock(&self) -> Option<Guard<'_, T>> {
+        if self
+            .lock
+            .compare_exchange(false, true, Acquire, Relaxed)
+            .is_ok()
+        {
+            return Some(Guard { inner: self });
+        }
+        None
+    }
+
+    pub fn try_lock_for(&self, timeout: Duration) -> Option<Guard<'_, T>> {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => self.try_lock_until(deadline),
+            // Too far in the future to represent, same as waiting forever
+            None => Some(self.lock()),
+        }
+    }
+
+    // Spin until the lock is taken or the deadline is passed
+    pub fn try_lock_until(&self, deadline: Instant) -> Option<Guard<'_, T>> {
+        loop {
+            if let Some(guard) = self.try_lock() {
+                return Some(guard);
+            }
+            if Instant::now() >= deadline {
+                return None;
+            }
+            // Only read while the lock is held, writing to the cache line slows down the holder
+            while self.lock.load(Relaxed) && Instant::now() < deadline {
+                std::hint::spin_loop();
+            }
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+
+    // &mut self guarantees no other reference to SpinLock, so no need to lock
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
 }
 
-struct Guard<'a, T> {
+impl<T: Default> Default for SpinLock<T> {
+    fn default() -> SpinLock<T> {
+        SpinLock::new(T::default())
+    }
+}
+
+// Can't read data without taking the lock, so only show the lock state
+impl<T> fmt::Debug for SpinLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SpinLock")
+            .field("locked", &self.lock.load(Relaxed))
+            .finish_non_exhaustive()
+    }
+}
+
+pub struct Guard<'a, T> {
     inner: &'a SpinLock<T>,
 }
 
+// Sharing &Guard between threads gives out &T, so T need to be Sync
+unsafe impl<T: Sync> Sync for Guard<'_, T> {}
+
 impl<T> Deref for Guard<'_, T> {
     type Target = T;
     fn deref(&self) -> &T {
@@ -45,6 +111,12 @@ impl<T> DerefMut for Guard<'_, T> {
     }
 }
 
+impl<T: fmt::Debug> fmt::Debug for Guard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
 // Guard prevent developer to explicitly unlock the SpinLock
 // But auto unlock when Drop
 impl<T> Drop for Guard<'_, T> {
@@ -56,6 +128,8 @@ impl<T> Drop for Guard<'_, T> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::chapter4_build_spin_lock::tests::racy_increment;
+    use std::sync::atomic::AtomicUsize;
     use std::thread;
 
     #[test]
@@ -73,4 +147,135 @@ mod tests {
         let guard = nums.lock();
         assert!(*guard == [1, 2, 2] || *guard == [2, 2, 1]);
     }
+
+    #[test]
+    fn test_blocks_while_held() {
+        let lock = SpinLock::new(0);
+        let entered = AtomicBool::new(false);
+
+        let guard = lock.lock();
+        thread::scope(|s| {
+            s.spawn(|| {
+                let mut guard = lock.lock();
+                entered.store(true, Relaxed);
+                *guard += 1;
+            });
+            thread::sleep(Duration::from_millis(50));
+            // Lock is still held by this thread, so the other one must not get in
+            assert!(!entered.load(Relaxed));
+            drop(guard);
+        });
+
+        assert!(entered.load(Relaxed));
+        assert_eq!(lock.into_inner(), 1);
+    }
+
+    #[test]
+    fn test_mutual_exclusion() {
+        const THREADS: u64 = 8;
+        const ITERATIONS: u64 = 2000;
+
+        let counter = SpinLock::new(0u64);
+        // Number of threads inside the critical section, must never be more than one
+        let inside = AtomicUsize::new(0);
+
+        thread::scope(|s| {
+            for _ in 0..THREADS {
+                s.spawn(|| {
+                    for _ in 0..ITERATIONS {
+                        let mut guard = counter.lock();
+                        assert_eq!(inside.fetch_add(1, Relaxed), 0);
+                        racy_increment(&mut guard);
+                        inside.fetch_sub(1, Relaxed);
+                    }
+                });
+            }
+        });
+
+        assert_eq!(counter.into_inner(), THREADS * ITERATIONS);
+    }
+
+    #[test]
+    fn test_mutual_exclusion_with_try_lock() {
+        const THREADS: u64 = 4;
+        const ITERATIONS: u64 = 2000;
+
+        let counter = SpinLock::new(0u64);
+
+        thread::scope(|s| {
+            for t in 0..THREADS {
+                let counter = &counter;
+                s.spawn(move || {
+                    for _ in 0..ITERATIONS {
+                        // Mix both ways of locking, they must exclude each other too
+                        let mut guard = if t % 2 == 0 {
+                            counter.lock()
+                        } else {
+                            loop {
+                                if let Some(guard) = counter.try_lock() {
+                                    break guard;
+                                }
+                                std::hint::spin_loop();
+                            }
+                        };
+                        racy_increment(&mut guard);
+                    }
+                });
+            }
+        });
+
+        assert_eq!(counter.into_inner(), THREADS * ITERATIONS);
+    }
+
+    #[test]
+    fn test_try_lock() {
+        let lock = SpinLock::new(0);
+
+        let mut guard = lock.try_lock().unwrap();
+        *guard += 1;
+        assert!(lock.try_lock().is_none());
+        drop(guard);
+
+        assert_eq!(*lock.try_lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_try_lock_contended() {
+        let lock = SpinLock::new(0);
+        let guard = lock.try_lock().unwrap();
+
+        thread::scope(|s| {
+            s.spawn(|| {
+                // Another thread holds the lock for the whole time
+                assert!(lock.try_lock().is_none());
+                let start = Instant::now();
+                assert!(lock.try_lock_for(Duration::from_millis(20)).is_none());
+                assert!(start.elapsed() >= Duration::from_millis(20));
+                assert!(lock.try_lock_until(Instant::now()).is_none());
+            })
+            .join()
+            .unwrap();
+
+            // Holder releases before the deadline, so the waiter gets the lock
+            let waiter = s.spawn(|| {
+                let mut guard = lock.try_lock_for(Duration::from_secs(10)).unwrap();
+                *guard += 1;
+            });
+            thread::sleep(Duration::from_millis(20));
+            drop(guard);
+            waiter.join().unwrap();
+        });
+
+        assert_eq!(lock.into_inner(), 1);
+    }
+
+    #[test]
+    fn test_try_lock_uncontended() {
+        let lock = SpinLock::new(0);
+        // Free lock is taken immediately, even with a deadline already passed
+        *lock.try_lock_for(Duration::ZERO).unwrap() += 1;
+        *lock.try_lock_until(Instant::now()).unwrap() += 1;
+        *lock.try_lock_for(Duration::MAX).unwrap() += 1;
+        assert_eq!(lock.into_inner(), 3);
+    }
 }
@@ -0,0 +1,203 @@
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+use std::thread;
+
+// Like the ticket machine at a bakery: take a number, wait until it's called
+// Threads get the lock in the order they took their ticket (FIFO), so nobody starves
+// Counters wrap around, which is fine as long as fewer than u32::MAX threads are waiting
+pub struct TicketLock<T> {
+    next_ticket: AtomicU32,
+    now_serving: AtomicU32,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for TicketLock<T> {}
+
+impl<T> TicketLock<T> {
+    pub const fn new(val: T) -> TicketLock<T> {
+        TicketLock {
+            next_ticket: AtomicU32::new(0),
+            now_serving: AtomicU32::new(0),
+            data: UnsafeCell::new(val),
+        }
+    }
+
+    pub fn lock(&self) -> Guard<'_, T> {
+        let ticket = self.next_ticket.fetch_add(1, Relaxed);
+        let mut spin_count = 0;
+        while self.now_serving.load(Acquire) != ticket {
+            // Only the thread with the next ticket can make progress
+            // If it's not running (more threads than cores), spinning just wastes its time slice
+            if spin_count < 100 {
+                spin_count += 1;
+                std::hint::spin_loop();
+            } else {
+                thread::yield_now();
+            }
+        }
+        Guard { inner: self }
+    }
+
+    // Only take a ticket if it would be served right away
+    pub fn try_lock(&self) -> Option<Guard<'_, T>> {
+        // Acquire pairs with the Release in Guard::drop, like in lock
+        // The CAS on next_ticket can't give that, next_ticket is only ever changed with Relaxed
+        let serving = self.now_serving.load(Acquire);
+        if self
+            .next_ticket
+            .compare_exchange(serving, serving.wrapping_add(1), Relaxed, Relaxed)
+            .is_ok()
+        {
+            return Some(Guard { inner: self });
+        }
+        None
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+}
+
+impl<T: Default> Default for TicketLock<T> {
+    fn default() -> TicketLock<T> {
+        TicketLock::new(T::default())
+    }
+}
+
+impl<T> fmt::Debug for TicketLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TicketLock")
+            .field("next_ticket", &self.next_ticket.load(Relaxed))
+            .field("now_serving", &self.now_serving.load(Relaxed))
+            .finish_non_exhaustive()
+    }
+}
+
+pub struct Guard<'a, T> {
+    inner: &'a TicketLock<T>,
+}
+
+unsafe impl<T: Sync> Sync for Guard<'_, T> {}
+
+impl<T> Deref for Guard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.inner.data.get() }
+    }
+}
+
+impl<T> DerefMut for Guard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.inner.data.get() }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Guard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+// Call the next ticket
+impl<T> Drop for Guard<'_, T> {
+    fn drop(&mut self) {
+        self.inner.now_serving.fetch_add(1, Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chapter4_build_spin_lock::tests::{
+        check_guard, check_mutual_exclusion, racy_increment,
+    };
+    use std::time::Duration;
+
+    #[test]
+    fn test() {
+        let nums = TicketLock::new(Vec::new());
+        check_guard(|| nums.lock());
+    }
+
+    #[test]
+    fn test_mutual_exclusion() {
+        let counter = TicketLock::new(0u64);
+        check_mutual_exclusion(|| counter.lock());
+    }
+
+    #[test]
+    fn test_fifo() {
+        const THREADS: u32 = 8;
+
+        let order = TicketLock::new(Vec::new());
+        let guard = order.lock();
+
+        thread::scope(|s| {
+            for id in 0..THREADS {
+                let order = &order;
+                s.spawn(move || order.lock().push(id));
+                // Wait until this thread took its ticket before starting the next one,
+                // so the ticket order is the spawn order
+                while order.next_ticket.load(Relaxed) != id + 2 {
+                    thread::sleep(Duration::from_millis(1));
+                }
+            }
+            assert!(order.try_lock().is_none());
+            drop(guard);
+        });
+
+        // Threads are served in the order they started waiting
+        assert_eq!(order.into_inner(), (0..THREADS).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_try_lock() {
+        let lock = TicketLock::new(0);
+
+        let mut guard = lock.try_lock().unwrap();
+        *guard += 1;
+        assert!(lock.try_lock().is_none());
+        drop(guard);
+
+        *lock.lock() += 1;
+        assert_eq!(*lock.try_lock().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_try_lock_mutual_exclusion() {
+        const THREADS: u64 = 4;
+        const ITERATIONS: u64 = 2000;
+
+        let counter = TicketLock::new(0u64);
+        thread::scope(|s| {
+            for _ in 0..THREADS {
+                s.spawn(|| {
+                    for _ in 0..ITERATIONS {
+                        racy_increment(&mut counter.lock());
+                    }
+                });
+            }
+            // Mixed with lock: only counts when try_lock got it
+            s.spawn(|| {
+                let mut done = 0;
+                while done < ITERATIONS {
+                    if let Some(mut guard) = counter.try_lock() {
+                        racy_increment(&mut guard);
+                        done += 1;
+                    } else {
+                        thread::yield_now();
+                    }
+                }
+            });
+        });
+
+        assert_eq!(counter.into_inner(), (THREADS + 1) * ITERATIONS);
+    }
+}
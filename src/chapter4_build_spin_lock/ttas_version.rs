@@ -0,0 +1,236 @@
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+use std::thread;
+
+// Test-and-test-and-set: wait with plain loads, which keep the cache line shared between waiters,
+// and only try to write (compare_exchange) once the lock looks free
+// safe_version swaps on every iteration, each swap takes the cache line exclusive and slows the holder down
+
+// How long to wait between attempts, doubled after every failed attempt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+    // Spin 2^step times per attempt, step grows up to spin_limit (at most MAX_SPIN_LIMIT)
+    pub spin_limit: u32,
+    // Once spin_limit is reached, give the CPU back to the OS scheduler instead of spinning
+    // Useful when there are more threads than cores and the holder might not be running
+    pub yield_when_exhausted: bool,
+}
+
+// 2^31 is the most spins a u32 counts, a bigger spin_limit is treated as this
+const MAX_SPIN_LIMIT: u32 = 31;
+
+const DEFAULT_BACKOFF: Backoff = Backoff {
+    spin_limit: 6,
+    yield_when_exhausted: true,
+};
+
+impl Backoff {
+    // Always spin, never yield
+    pub const fn spin_only(spin_limit: u32) -> Backoff {
+        Backoff {
+            spin_limit,
+            yield_when_exhausted: false,
+        }
+    }
+
+    pub(crate) fn snooze(&self, step: &mut u32) {
+        if *step < self.spin_limit.min(MAX_SPIN_LIMIT) {
+            *step += 1;
+        } else if self.yield_when_exhausted {
+            thread::yield_now();
+            return;
+        }
+        for _ in 0..1u32 << *step {
+            std::hint::spin_loop();
+        }
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Backoff {
+        DEFAULT_BACKOFF
+    }
+}
+
+pub struct SpinLock<T> {
+    lock: AtomicBool,
+    backoff: Backoff,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for SpinLock<T> {}
+
+impl<T> SpinLock<T> {
+    pub const fn new(val: T) -> SpinLock<T> {
+        SpinLock::with_backoff(val, DEFAULT_BACKOFF)
+    }
+
+    pub const fn with_backoff(val: T, backoff: Backoff) -> SpinLock<T> {
+        SpinLock {
+            lock: AtomicBool::new(false),
+            backoff,
+            data: UnsafeCell::new(val),
+        }
+    }
+
+    pub fn lock(&self) -> Guard<'_, T> {
+        let mut step = 0;
+        loop {
+            // Test: read only while the lock is held
+            while self.lock.load(Relaxed) {
+                self.backoff.snooze(&mut step);
+            }
+            // Test-and-set: lock looked free, but another thread might take it first
+            if self
+                .lock
+                .compare_exchange_weak(false, true, Acquire, Relaxed)
+                .is_ok()
+            {
+                return Guard { inner: self };
+            }
+            self.backoff.snooze(&mut step);
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<Guard<'_, T>> {
+        if self
+            .lock
+            .compare_exchange(false, true, Acquire, Relaxed)
+            .is_ok()
+        {
+            return Some(Guard { inner: self });
+        }
+        None
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+}
+
+impl<T: Default> Default for SpinLock<T> {
+    fn default() -> SpinLock<T> {
+        SpinLock::new(T::default())
+    }
+}
+
+impl<T> fmt::Debug for SpinLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SpinLock")
+            .field("locked", &self.lock.load(Relaxed))
+            .field("backoff", &self.backoff)
+            .finish_non_exhaustive()
+    }
+}
+
+pub struct Guard<'a, T> {
+    inner: &'a SpinLock<T>,
+}
+
+unsafe impl<T: Sync> Sync for Guard<'_, T> {}
+
+impl<T> Deref for Guard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.inner.data.get() }
+    }
+}
+
+impl<T> DerefMut for Guard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.inner.data.get() }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Guard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T> Drop for Guard<'_, T> {
+    fn drop(&mut self) {
+        self.inner.lock.store(false, Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chapter4_build_spin_lock::tests::{check_guard, check_mutual_exclusion};
+    use std::time::Duration;
+
+    #[test]
+    fn test() {
+        let nums = SpinLock::new(Vec::new());
+        check_guard(|| nums.lock());
+    }
+
+    #[test]
+    fn test_blocks_while_held() {
+        let lock = SpinLock::new(0);
+        let entered = AtomicBool::new(false);
+
+        let guard = lock.lock();
+        assert!(lock.try_lock().is_none());
+        thread::scope(|s| {
+            s.spawn(|| {
+                *lock.lock() += 1;
+                entered.store(true, Relaxed);
+            });
+            thread::sleep(Duration::from_millis(50));
+            assert!(!entered.load(Relaxed));
+            drop(guard);
+        });
+
+        assert!(entered.load(Relaxed));
+        assert_eq!(lock.into_inner(), 1);
+    }
+
+    #[test]
+    fn test_mutual_exclusion() {
+        for backoff in [Backoff::default(), Backoff::spin_only(4)] {
+            let counter = SpinLock::with_backoff(0u64, backoff);
+            check_mutual_exclusion(|| counter.lock());
+        }
+    }
+
+    #[test]
+    fn test_backoff() {
+        let backoff = Backoff {
+            spin_limit: 3,
+            yield_when_exhausted: true,
+        };
+        let mut step = 0;
+        for expected in [1, 2, 3, 3, 3] {
+            backoff.snooze(&mut step);
+            assert_eq!(step, expected);
+        }
+
+        let mut step = 0;
+        for _ in 0..10 {
+            Backoff::spin_only(3).snooze(&mut step);
+        }
+        assert_eq!(step, 3);
+    }
+
+    #[test]
+    fn test_backoff_large_spin_limit() {
+        // Would shift past the width of u32 without the clamp
+        // Starts at the cap, yields there instead of spinning 2^31 times
+        let backoff = Backoff {
+            spin_limit: 40,
+            yield_when_exhausted: true,
+        };
+        let mut step = MAX_SPIN_LIMIT;
+        backoff.snooze(&mut step);
+        assert_eq!(step, MAX_SPIN_LIMIT);
+    }
+}
@@ -8,7 +8,7 @@ struct SpinLock<T> {
 }
 
 // Allow SpinLock to be shared between threads
-unsafe impl<T> Sync for SpinLock<T> {}
+unsafe impl<T: Send> Sync for SpinLock<T> {}
 
 impl<T> SpinLock<T> {
     pub const fn new(val: T) -> SpinLock<T> {
@@ -19,7 +19,7 @@ impl<T> SpinLock<T> {
     }
 
     pub fn lock(&mut self) -> &mut T {
-        while !self.lock.swap(true, Acquire) {
+        while self.lock.swap(true, Acquire) {
             std::hint::spin_loop();
         }
 
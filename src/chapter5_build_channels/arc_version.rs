@@ -0,0 +1,365 @@
+use crate::chapter5_build_channels::deadline::{deadline, park_until};
+use crate::chapter5_build_channels::error::{RecvError, RecvTimeoutError, SendError, TryRecvError};
+use crate::chapter6_build_arc::weak_pointer::Arc;
+use std::cell::{Cell, UnsafeCell};
+use std::fmt;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::mem::MaybeUninit;
+use std::pin::Pin;
+use std::ptr;
+use std::sync::atomic::Ordering::{Acquire, Relaxed, SeqCst};
+use std::sync::atomic::{AtomicBool, AtomicPtr};
+use std::task::{Context, Poll, Waker};
+use std::thread::{self, Thread};
+use std::time::{Duration, Instant};
+
+// Same one-shot channel as type_safe_version, but the state lives in an Arc instead of being
+// borrowed, so both halves are 'static and can be moved into thread::spawn or returned
+// Receiver can move between threads too, so it can't remember its thread up front:
+// it registers the current thread every time it's about to wait
+// Receiver is also a Future, then it registers the task's waker instead of the thread
+
+// Whoever is waiting for the message: a blocked thread or an async task
+enum Waiter {
+    Thread(Thread),
+    Task(Waker),
+}
+
+impl Waiter {
+    fn wake(self) {
+        match self {
+            Waiter::Thread(t) => t.unpark(),
+            Waiter::Task(waker) => waker.wake(),
+        }
+    }
+}
+
+// Replace the waiter, dropping the old one, which was ours from an earlier wait or poll
+fn set_waiter(slot: &AtomicPtr<Waiter>, waiter: Waiter) {
+    let old = slot.swap(Box::into_raw(Box::new(waiter)), SeqCst);
+    if !old.is_null() {
+        drop(unsafe { Box::from_raw(old) });
+    }
+}
+
+struct Shared<T> {
+    msg: UnsafeCell<MaybeUninit<T>>,
+    ready: AtomicBool,
+    // Boxed waiter of the receiver, whoever swaps it out owns it
+    waiter: AtomicPtr<Waiter>,
+    sender_dropped: AtomicBool,
+    receiver_dropped: AtomicBool,
+}
+
+unsafe impl<T: Send> Sync for Shared<T> {}
+
+impl<T> Shared<T> {
+    fn wake(&self) {
+        let t = self.waiter.swap(ptr::null_mut(), SeqCst);
+        if !t.is_null() {
+            unsafe { Box::from_raw(t) }.wake();
+        }
+    }
+}
+
+impl<T> Drop for Shared<T> {
+    fn drop(&mut self) {
+        if *self.ready.get_mut() {
+            unsafe { self.msg.get_mut().assume_init_drop() };
+        }
+        let t = *self.waiter.get_mut();
+        if !t.is_null() {
+            drop(unsafe { Box::from_raw(t) });
+        }
+    }
+}
+
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        msg: UnsafeCell::new(MaybeUninit::uninit()),
+        ready: AtomicBool::new(false),
+        waiter: AtomicPtr::new(ptr::null_mut()),
+        sender_dropped: AtomicBool::new(false),
+        receiver_dropped: AtomicBool::new(false),
+    });
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver {
+            shared,
+            _not_sync: PhantomData,
+        },
+    )
+}
+
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    // Takes self, so a second send doesn't compile
+    // Nobody would ever read the message, so give it back instead
+    pub fn send(self, msg: T) -> Result<(), SendError<T>> {
+        if self.shared.receiver_dropped.load(Acquire) {
+            return Err(SendError(msg));
+        }
+        unsafe { (*self.shared.msg.get()).write(msg) };
+        // Publish first, then wake up
+        self.shared.ready.store(true, SeqCst);
+        self.shared.wake();
+        Ok(())
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.shared.sender_dropped.store(true, SeqCst);
+        self.shared.wake();
+    }
+}
+
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sender").finish_non_exhaustive()
+    }
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+    // Can be sent but not shared (!Sync), there is only one waiter slot
+    _not_sync: PhantomData<Cell<()>>,
+}
+
+// Like the borrowed version, every way to receive takes self, so receiving twice doesn't compile
+// The ones that can fail before the message is there give the receiver back in the error
+impl<T> Receiver<T> {
+    // Block until the message is there
+    // RecvError if the sender is dropped without sending anything, same as awaiting it
+    pub fn recv(self) -> Result<T, RecvError> {
+        self.wait_until(None).map_err(|_| RecvError)
+    }
+
+    pub fn try_recv(self) -> Result<T, (Self, TryRecvError)> {
+        match self.poll_recv() {
+            Ok(msg) => Ok(msg),
+            Err(e) => Err((self, e)),
+        }
+    }
+
+    pub fn recv_timeout(self, timeout: Duration) -> Result<T, (Self, RecvTimeoutError)> {
+        match self.wait_until(deadline(timeout)) {
+            Ok(msg) => Ok(msg),
+            Err(e) => Err((self, e)),
+        }
+    }
+
+    pub fn recv_deadline(self, deadline: Instant) -> Result<T, (Self, RecvTimeoutError)> {
+        match self.wait_until(Some(deadline)) {
+            Ok(msg) => Ok(msg),
+            Err(e) => Err((self, e)),
+        }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.shared.ready.load(Relaxed)
+    }
+
+    fn poll_recv(&self) -> Result<T, TryRecvError> {
+        if self.shared.sender_dropped.load(SeqCst) {
+            // A message sent before the drop is visible now, take it or report the disconnection
+            return self.take().ok_or(TryRecvError::Disconnected);
+        }
+        self.take().ok_or(TryRecvError::Empty)
+    }
+
+    fn take(&self) -> Option<T> {
+        if !self.shared.ready.swap(false, SeqCst) {
+            return None;
+        }
+        Some(unsafe { (*self.shared.msg.get()).assume_init_read() })
+    }
+
+    fn wait_until(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        // Register before checking: the sender stores ready/sender_dropped before taking the waiter,
+        // so either we see its store or it sees our thread (all SeqCst)
+        set_waiter(&self.shared.waiter, Waiter::Thread(thread::current()));
+        let result = loop {
+            match self.poll_recv() {
+                Ok(msg) => break Ok(msg),
+                Err(TryRecvError::Disconnected) => break Err(RecvTimeoutError::Disconnected),
+                Err(TryRecvError::Empty) => {}
+            }
+            if !park_until(deadline) {
+                break Err(RecvTimeoutError::Timeout);
+            }
+        };
+        // Take our thread back if the sender didn't
+        let t = self.shared.waiter.swap(ptr::null_mut(), SeqCst);
+        if !t.is_null() {
+            drop(unsafe { Box::from_raw(t) });
+        }
+        result
+    }
+}
+
+// Resolves to the message, or RecvError if the sender is dropped without sending
+impl<T> Future for Receiver<T> {
+    type Output = Result<T, RecvError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Same as wait_until: register first, then check, so a send in between can't be missed
+        set_waiter(&self.shared.waiter, Waiter::Task(cx.waker().clone()));
+        match self.poll_recv() {
+            Ok(msg) => Poll::Ready(Ok(msg)),
+            Err(TryRecvError::Disconnected) => Poll::Ready(Err(RecvError)),
+            Err(TryRecvError::Empty) => Poll::Pending,
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.receiver_dropped.store(true, SeqCst);
+    }
+}
+
+impl<T> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Receiver")
+            .field("ready", &self.is_ready())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chapter5_build_channels::executor::block_on;
+
+    #[test]
+    fn test() {
+        let (sender, receiver) = channel();
+        let t = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            sender.send("hello").unwrap();
+        });
+        assert_eq!(receiver.recv(), Ok("hello"));
+        t.join().unwrap();
+    }
+
+    #[test]
+    fn test_move_receiver_to_other_thread() {
+        fn make() -> (Sender<u32>, Receiver<u32>) {
+            channel()
+        }
+
+        let (sender, receiver) = make();
+        let t = thread::spawn(move || receiver.recv());
+        thread::sleep(Duration::from_millis(20));
+        sender.send(1).unwrap();
+        assert_eq!(t.join().unwrap(), Ok(1));
+    }
+
+    #[test]
+    fn test_receiver_dropped() {
+        let (sender, receiver) = channel();
+        drop(receiver);
+        assert_eq!(sender.send("hello"), Err(SendError("hello")));
+    }
+
+    #[test]
+    fn test_sender_dropped() {
+        let (sender, receiver) = channel::<i32>();
+        let (receiver, e) = receiver.try_recv().unwrap_err();
+        assert_eq!(e, TryRecvError::Empty);
+        drop(sender);
+        let (_, e) = receiver.try_recv().unwrap_err();
+        assert_eq!(e, TryRecvError::Disconnected);
+
+        let (sender, receiver) = channel::<i32>();
+        let t = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            drop(sender);
+        });
+        let (_, e) = receiver.recv_timeout(Duration::from_secs(10)).unwrap_err();
+        assert_eq!(e, RecvTimeoutError::Disconnected);
+        t.join().unwrap();
+    }
+
+    #[test]
+    fn test_recv_sender_dropped() {
+        let (sender, receiver) = channel::<i32>();
+        drop(sender);
+        assert_eq!(receiver.recv(), Err(RecvError));
+    }
+
+    #[test]
+    fn test_recv_timeout() {
+        let (sender, receiver) = channel();
+        let (receiver, e) = receiver
+            .recv_timeout(Duration::from_millis(20))
+            .unwrap_err();
+        assert_eq!(e, RecvTimeoutError::Timeout);
+        // Timed out receiver is given back and can wait again
+        let t = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            sender.send(1).unwrap();
+        });
+        let deadline = Instant::now() + Duration::from_secs(10);
+        assert_eq!(receiver.recv_deadline(deadline).unwrap(), 1);
+        t.join().unwrap();
+    }
+
+    #[test]
+    fn test_try_recv() {
+        let (sender, receiver) = channel();
+        sender.send(1).unwrap();
+        // Ok consumes the receiver, there is no second try_recv to get Disconnected from
+        assert_eq!(receiver.try_recv().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_drop_unreceived_message() {
+        let msg = std::sync::Arc::new(());
+        let (sender, receiver) = channel();
+        sender.send(msg.clone()).unwrap();
+        drop(receiver);
+        assert_eq!(std::sync::Arc::strong_count(&msg), 1);
+    }
+
+    #[test]
+    fn test_async() {
+        let (sender, receiver) = channel();
+        let t = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            sender.send("hello").unwrap();
+        });
+        assert_eq!(block_on(receiver), Ok("hello"));
+        t.join().unwrap();
+
+        let (sender, receiver) = channel::<i32>();
+        let t = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            drop(sender);
+        });
+        assert_eq!(block_on(receiver), Err(RecvError));
+        t.join().unwrap();
+    }
+
+    #[test]
+    fn test_poll_then_block() {
+        let (sender, mut receiver) = channel();
+        // Leaves a task waker registered, the blocking wait replaces it with the thread
+        let waker = Waker::noop();
+        let poll = Pin::new(&mut receiver).poll(&mut Context::from_waker(waker));
+        assert_eq!(poll, Poll::Pending);
+        let t = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            sender.send(1).unwrap();
+        });
+        assert_eq!(receiver.recv(), Ok(1));
+        t.join().unwrap();
+    }
+}
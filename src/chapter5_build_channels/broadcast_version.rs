@@ -0,0 +1,268 @@
+use crate::chapter5_build_channels::error::{BroadcastRecvError, BroadcastTryRecvError, SendError};
+use crate::chapter6_build_arc::weak_pointer::Arc;
+use crate::chapter9_build_locks::condvar::Condvar;
+use crate::chapter9_build_locks::mutex::Mutex;
+use std::collections::VecDeque;
+use std::fmt;
+
+// Every receiver gets every message (T: Clone), instead of each message going to one receiver
+// The last `capacity` messages are kept, each receiver remembers the position of the next one to read
+// A slow receiver never blocks the sender: its unread messages get overwritten and it's told
+// how many it missed (Lagged), then it continues from the oldest message still there
+struct State<T> {
+    messages: VecDeque<T>,
+    // Position of messages[0], positions count every message ever sent
+    head: u64,
+    senders: usize,
+    receivers: usize,
+}
+
+impl<T> State<T> {
+    // Position the next sent message gets
+    fn tail(&self) -> u64 {
+        self.head + self.messages.len() as u64
+    }
+}
+
+impl<T: Clone> State<T> {
+    // Message at position next for a receiver, moves next forward
+    fn read(&self, next: &mut u64) -> Result<T, BroadcastTryRecvError> {
+        if *next < self.head {
+            let missed = self.head - *next;
+            *next = self.head;
+            return Err(BroadcastTryRecvError::Lagged(missed));
+        }
+        if let Some(msg) = self.messages.get((*next - self.head) as usize) {
+            *next += 1;
+            return Ok(msg.clone());
+        }
+        // Everything is read, messages sent before the disconnection are delivered first
+        if self.senders == 0 {
+            return Err(BroadcastTryRecvError::Disconnected);
+        }
+        Err(BroadcastTryRecvError::Empty)
+    }
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    capacity: usize,
+    // Receivers that read everything wait here
+    not_empty: Condvar,
+}
+
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "capacity must be greater than zero");
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            messages: VecDeque::with_capacity(capacity),
+            head: 0,
+            senders: 1,
+            receivers: 1,
+        }),
+        capacity,
+        not_empty: Condvar::new(),
+    });
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared, next: 0 },
+    )
+}
+
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    // Never blocks, fails only when there is no receiver at all
+    pub fn send(&self, msg: T) -> Result<(), SendError<T>> {
+        let mut state = self.shared.state.lock();
+        if state.receivers == 0 {
+            return Err(SendError(msg));
+        }
+        if state.messages.len() == self.shared.capacity {
+            // Overwrite the oldest, receivers that didn't read it yet will see they lagged
+            state.messages.pop_front();
+            state.head += 1;
+        }
+        state.messages.push_back(msg);
+        drop(state);
+        self.shared.not_empty.notify_all();
+        Ok(())
+    }
+
+    // New receiver only sees messages sent from now on
+    pub fn subscribe(&self) -> Receiver<T> {
+        let mut state = self.shared.state.lock();
+        state.receivers += 1;
+        Receiver {
+            shared: self.shared.clone(),
+            next: state.tail(),
+        }
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Sender<T> {
+        self.shared.state.lock().senders += 1;
+        Sender {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock();
+        state.senders -= 1;
+        if state.senders == 0 {
+            drop(state);
+            self.shared.not_empty.notify_all();
+        }
+    }
+}
+
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sender").finish_non_exhaustive()
+    }
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+    // Position of the next message to read
+    next: u64,
+}
+
+impl<T: Clone> Receiver<T> {
+    pub fn recv(&mut self) -> Result<T, BroadcastRecvError> {
+        let mut state = self.shared.state.lock();
+        loop {
+            match state.read(&mut self.next) {
+                Ok(msg) => return Ok(msg),
+                Err(BroadcastTryRecvError::Lagged(n)) => return Err(BroadcastRecvError::Lagged(n)),
+                Err(BroadcastTryRecvError::Disconnected) => {
+                    return Err(BroadcastRecvError::Disconnected)
+                }
+                Err(BroadcastTryRecvError::Empty) => {}
+            }
+            state = self.shared.not_empty.wait(state);
+        }
+    }
+
+    pub fn try_recv(&mut self) -> Result<T, BroadcastTryRecvError> {
+        self.shared.state.lock().read(&mut self.next)
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.state.lock().receivers -= 1;
+    }
+}
+
+impl<T> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Receiver")
+            .field("next", &self.next)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test() {
+        let (sender, mut receiver1) = channel(4);
+        let mut receiver2 = sender.subscribe();
+        sender.send(1).unwrap();
+        sender.send(2).unwrap();
+
+        // Both receivers see every message, in order
+        assert_eq!(receiver1.recv(), Ok(1));
+        assert_eq!(receiver1.recv(), Ok(2));
+        assert_eq!(receiver2.recv(), Ok(1));
+        assert_eq!(receiver2.recv(), Ok(2));
+        assert_eq!(receiver1.try_recv(), Err(BroadcastTryRecvError::Empty));
+    }
+
+    #[test]
+    fn test_lagged() {
+        let (sender, mut receiver) = channel(2);
+        for i in 0..5 {
+            // Sender never waits for the slow receiver
+            sender.send(i).unwrap();
+        }
+        assert_eq!(receiver.recv(), Err(BroadcastRecvError::Lagged(3)));
+        // Continues from the oldest message still kept
+        assert_eq!(receiver.recv(), Ok(3));
+        assert_eq!(receiver.try_recv(), Ok(4));
+        assert_eq!(receiver.try_recv(), Err(BroadcastTryRecvError::Empty));
+    }
+
+    #[test]
+    fn test_subscribe_starts_at_tail() {
+        let (sender, mut receiver1) = channel(4);
+        sender.send(1).unwrap();
+        let mut receiver2 = sender.subscribe();
+        sender.send(2).unwrap();
+
+        assert_eq!(receiver1.recv(), Ok(1));
+        assert_eq!(receiver1.recv(), Ok(2));
+        // Subscribed after the first message, so it only sees the second
+        assert_eq!(receiver2.recv(), Ok(2));
+        assert_eq!(receiver2.try_recv(), Err(BroadcastTryRecvError::Empty));
+    }
+
+    #[test]
+    fn test_disconnect() {
+        let (sender, mut receiver) = channel(4);
+        sender.send(1).unwrap();
+        drop(sender);
+        assert_eq!(receiver.recv(), Ok(1));
+        assert_eq!(receiver.recv(), Err(BroadcastRecvError::Disconnected));
+
+        let (sender, receiver) = channel(4);
+        drop(receiver);
+        assert_eq!(sender.send(1), Err(SendError(1)));
+        // A new subscriber makes sending possible again
+        let mut receiver = sender.subscribe();
+        sender.send(2).unwrap();
+        assert_eq!(receiver.try_recv(), Ok(2));
+    }
+
+    #[test]
+    fn test_between_threads() {
+        const RECEIVERS: usize = 4;
+        const MESSAGES: u64 = 1000;
+
+        let (sender, receiver) = channel(MESSAGES as usize);
+        let receivers: Vec<_> = (1..RECEIVERS)
+            .map(|_| sender.subscribe())
+            .chain([receiver])
+            .collect();
+
+        thread::scope(|s| {
+            for mut receiver in receivers {
+                s.spawn(move || {
+                    // Big enough buffer, so nobody lags
+                    for i in 0..MESSAGES {
+                        assert_eq!(receiver.recv(), Ok(i));
+                    }
+                    assert_eq!(receiver.recv(), Err(BroadcastRecvError::Disconnected));
+                });
+            }
+            thread::sleep(Duration::from_millis(20));
+            for i in 0..MESSAGES {
+                sender.send(i).unwrap();
+            }
+            drop(sender);
+        });
+    }
+}
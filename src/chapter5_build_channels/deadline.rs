@@ -0,0 +1,28 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Shared by the recv_timeout and recv_deadline of every channel
+
+// Deadline of a timeout starting now
+// None when it's too far in the future to represent (e.g. Duration::MAX),
+// the channels treat that as having no deadline at all and wait forever
+pub(crate) fn deadline(timeout: Duration) -> Option<Instant> {
+    Instant::now().checked_add(timeout)
+}
+
+// Park until unparked or until the deadline, None parks without a deadline
+// Returns false right away, without parking, once the deadline has passed
+// park can also return spuriously, so callers check their condition again in a loop
+pub(crate) fn park_until(deadline: Option<Instant>) -> bool {
+    match deadline {
+        None => thread::park(),
+        Some(deadline) => {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            thread::park_timeout(deadline - now);
+        }
+    }
+    true
+}
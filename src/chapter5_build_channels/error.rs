@@ -0,0 +1,126 @@
+use std::error::Error;
+use std::fmt;
+
+// All receivers are gone, the message is handed back to the caller
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub struct SendError<T>(pub T);
+
+// All senders are gone and no message is left
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct RecvError;
+
+impl<T> SendError<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+// Don't require T: Debug, the message is not interesting for the error itself
+impl<T> fmt::Debug for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SendError").finish_non_exhaustive()
+    }
+}
+
+impl<T> fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("sending on a disconnected channel")
+    }
+}
+
+impl<T> Error for SendError<T> {}
+
+impl fmt::Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("receiving on an empty and disconnected channel")
+    }
+}
+
+impl Error for RecvError {}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TryRecvError {
+    // Nothing to receive right now, but a sender might still send something
+    Empty,
+    Disconnected,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum RecvTimeoutError {
+    Timeout,
+    Disconnected,
+}
+
+impl From<RecvError> for TryRecvError {
+    fn from(_: RecvError) -> TryRecvError {
+        TryRecvError::Disconnected
+    }
+}
+
+impl From<RecvError> for RecvTimeoutError {
+    fn from(_: RecvError) -> RecvTimeoutError {
+        RecvTimeoutError::Disconnected
+    }
+}
+
+impl fmt::Display for TryRecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryRecvError::Empty => f.write_str("receiving on an empty channel"),
+            TryRecvError::Disconnected => fmt::Display::fmt(&RecvError, f),
+        }
+    }
+}
+
+impl Error for TryRecvError {}
+
+impl fmt::Display for RecvTimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecvTimeoutError::Timeout => f.write_str("timed out waiting on channel"),
+            RecvTimeoutError::Disconnected => fmt::Display::fmt(&RecvError, f),
+        }
+    }
+}
+
+impl Error for RecvTimeoutError {}
+
+// Broadcast receiver fell behind and the oldest messages were overwritten
+// Holds how many messages it missed, the next receive gets the oldest one still there
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum BroadcastRecvError {
+    Lagged(u64),
+    Disconnected,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum BroadcastTryRecvError {
+    Empty,
+    Lagged(u64),
+    Disconnected,
+}
+
+impl fmt::Display for BroadcastRecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BroadcastRecvError::Lagged(n) => write!(f, "receiver lagged behind by {n} messages"),
+            BroadcastRecvError::Disconnected => fmt::Display::fmt(&RecvError, f),
+        }
+    }
+}
+
+impl Error for BroadcastRecvError {}
+
+impl fmt::Display for BroadcastTryRecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BroadcastTryRecvError::Empty => fmt::Display::fmt(&TryRecvError::Empty, f),
+            BroadcastTryRecvError::Lagged(n) => {
+                fmt::Display::fmt(&BroadcastRecvError::Lagged(*n), f)
+            }
+            BroadcastTryRecvError::Disconnected => fmt::Display::fmt(&RecvError, f),
+        }
+    }
+}
+
+impl Error for BroadcastTryRecvError {}
@@ -1,4 +1,17 @@
+pub(crate) mod arc_version;
+pub(crate) mod broadcast_version;
+pub(crate) mod deadline;
+pub(crate) mod error;
+#[cfg(test)]
+pub(crate) mod executor;
+pub(crate) mod mpmc_version;
 mod panic_safe_version;
-mod simple_version;
-mod type_safe_version;
+pub(crate) mod priority_version;
+pub(crate) mod rendezvous_version;
+pub(crate) mod ring_buffer_version;
+pub(crate) mod select;
+pub(crate) mod simple_version;
+pub(crate) mod spsc_version;
+pub(crate) mod stats;
+pub(crate) mod type_safe_version;
 mod unsafe_version;
@@ -0,0 +1,945 @@
+use crate::chapter5_build_channels::deadline::deadline;
+use crate::chapter5_build_channels::error::{RecvError, RecvTimeoutError, SendError, TryRecvError};
+use crate::chapter5_build_channels::stats::{self, Blocked, ChannelStats, Op, Stats};
+use crate::chapter6_build_arc::weak_pointer::Arc;
+use crate::chapter9_build_locks::condvar::Condvar;
+use crate::chapter9_build_locks::mutex::{Mutex, MutexGuard};
+use std::collections::VecDeque;
+use std::fmt;
+use std::future::Future;
+use std::mem;
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+use std::thread::{Thread, ThreadId};
+use std::time::{Duration, Instant};
+
+// Same idea as simple_version::Channel (queue behind a mutex), but:
+// - Capacity is limited, send blocks while the queue is full (back-pressure),
+//   so a fast producer can't grow the queue forever
+// - Sender and Receiver handles can be cloned and moved to other threads
+// - Handles are counted, so each side knows when the other side is gone
+struct State<T> {
+    queue: VecDeque<T>,
+    // Counted under the same lock as the queue, so a waiter can't miss the last handle being dropped
+    senders: usize,
+    receivers: usize,
+    // Threads blocked in Select waiting on this channel among others
+    // They don't wait on not_empty (they can only wait on one condvar), so they are unparked instead
+    selectors: Vec<Thread>,
+    // Tasks waiting in recv_async/send_async, a task can't block on a condvar either
+    // Woken and removed on every change, a task that is still waiting registers again when polled
+    recv_wakers: Vec<Waker>,
+    send_wakers: Vec<Waker>,
+}
+
+// recv has to wait: nothing queued, but a sender could still send something
+fn empty<T>(state: &mut State<T>) -> bool {
+    state.queue.is_empty() && state.senders > 0
+}
+
+// Polling again with the same waker shouldn't grow the list
+fn register(wakers: &mut Vec<Waker>, waker: &Waker) {
+    if !wakers.iter().any(|w| w.will_wake(waker)) {
+        wakers.push(waker.clone());
+    }
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    capacity: usize,
+    // Receivers wait here while the queue is empty
+    not_empty: Condvar,
+    // Senders wait here while the queue is full
+    not_full: Condvar,
+    // Only kept for channels made with the *_with_stats functions
+    stats: Option<Stats>,
+}
+
+impl<T> Shared<T> {
+    fn push(&self, state: &mut State<T>, msg: T) {
+        state.queue.push_back(msg);
+        if let Some(stats) = &self.stats {
+            stats.record_send(state.queue.len());
+        }
+    }
+
+    fn pop(&self, state: &mut State<T>) -> Option<T> {
+        let msg = state.queue.pop_front()?;
+        if let Some(stats) = &self.stats {
+            stats.record_recv(1);
+        }
+        Some(msg)
+    }
+
+    fn blocked(&self, op: Op, waits: bool) -> Option<Blocked<'_>> {
+        stats::blocked(self.stats.as_ref(), op, waits)
+    }
+
+    fn len(&self) -> usize {
+        self.state.lock().queue.len()
+    }
+
+    fn capacity(&self) -> Option<usize> {
+        // unbounded uses usize::MAX, a queue that long can't exist anyway
+        (self.capacity != usize::MAX).then_some(self.capacity)
+    }
+
+    // A message was pushed, or all senders are gone: wake up every kind of waiting receiver
+    fn notify_receivers(&self, mut state: MutexGuard<'_, State<T>>, all: bool) {
+        for t in &state.selectors {
+            t.unpark();
+        }
+        let wakers = mem::take(&mut state.recv_wakers);
+        drop(state);
+        if all {
+            self.not_empty.notify_all();
+        } else {
+            self.not_empty.notify_one();
+        }
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    // A message was popped, or all receivers are gone: wake up every kind of waiting sender
+    fn notify_senders(&self, mut state: MutexGuard<'_, State<T>>, all: bool) {
+        let wakers = mem::take(&mut state.send_wakers);
+        drop(state);
+        if all {
+            self.not_full.notify_all();
+        } else {
+            self.not_full.notify_one();
+        }
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
+
+pub fn bounded<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "capacity must be greater than zero");
+    new_channel(capacity, VecDeque::with_capacity(capacity), None)
+}
+
+// Never blocks on send, like simple_version::Channel but disconnected by dropping the handles instead of close
+pub fn unbounded<T>() -> (Sender<T>, Receiver<T>) {
+    new_channel(usize::MAX, VecDeque::new(), None)
+}
+
+// Same channels, but counting what goes through them, see Sender::stats and Receiver::stats
+pub fn bounded_with_stats<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "capacity must be greater than zero");
+    new_channel(
+        capacity,
+        VecDeque::with_capacity(capacity),
+        Some(Stats::new()),
+    )
+}
+
+pub fn unbounded_with_stats<T>() -> (Sender<T>, Receiver<T>) {
+    new_channel(usize::MAX, VecDeque::new(), Some(Stats::new()))
+}
+
+fn new_channel<T>(
+    capacity: usize,
+    queue: VecDeque<T>,
+    stats: Option<Stats>,
+) -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            queue,
+            senders: 1,
+            receivers: 1,
+            selectors: Vec::new(),
+            recv_wakers: Vec::new(),
+            send_wakers: Vec::new(),
+        }),
+        capacity,
+        not_empty: Condvar::new(),
+        not_full: Condvar::new(),
+        stats,
+    });
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared },
+    )
+}
+
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    // Fail only when every receiver is gone, the message is given back in the error
+    pub fn send(&self, msg: T) -> Result<(), SendError<T>> {
+        let mut state = self.shared.state.lock();
+        let full =
+            |state: &mut State<T>| state.queue.len() == self.shared.capacity && state.receivers > 0;
+        let _blocked = self.shared.blocked(Op::Send, full(&mut state));
+        let mut state = self.shared.not_full.wait_while(state, full);
+        if state.receivers == 0 {
+            return Err(SendError(msg));
+        }
+        self.shared.push(&mut state, msg);
+        self.shared.notify_receivers(state, false);
+        Ok(())
+    }
+
+    // Same as send, but waits for room as a task instead of blocking the thread
+    pub fn send_async(&self, msg: T) -> SendFuture<'_, T> {
+        SendFuture {
+            sender: self,
+            msg: Some(msg),
+        }
+    }
+
+    // Number of messages queued right now, can be outdated as soon as it's returned
+    pub fn len(&self) -> usize {
+        self.shared.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // None for an unbounded channel
+    pub fn capacity(&self) -> Option<usize> {
+        self.shared.capacity()
+    }
+
+    // None unless made with bounded_with_stats or unbounded_with_stats, doesn't take the queue lock
+    pub fn stats(&self) -> Option<ChannelStats> {
+        self.shared.stats.as_ref().map(Stats::snapshot)
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Sender<T> {
+        self.shared.state.lock().senders += 1;
+        Sender {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock();
+        state.senders -= 1;
+        if state.senders == 0 {
+            // Wake up every blocked receiver so they can see the disconnection
+            self.shared.notify_receivers(state, true);
+        }
+    }
+}
+
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sender").finish_non_exhaustive()
+    }
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Receiver<T> {
+    // Messages sent before the disconnection are still delivered, fail only when the queue is drained
+    pub fn recv(&self) -> Result<T, RecvError> {
+        let mut state = self.shared.state.lock();
+        let _blocked = self.shared.blocked(Op::Recv, empty(&mut state));
+        let mut state = self.shared.not_empty.wait_while(state, empty);
+        let msg = self.shared.pop(&mut state).ok_or(RecvError)?;
+        self.shared.notify_senders(state, false);
+        Ok(msg)
+    }
+
+    // Block until there is at least one message, then move up to max of them into buf
+    // under a single lock, return how many were moved
+    pub fn recv_many(&self, buf: &mut Vec<T>, max: usize) -> Result<usize, RecvError> {
+        if max == 0 {
+            return Ok(0);
+        }
+        let mut state = self.shared.state.lock();
+        let _blocked = self.shared.blocked(Op::Recv, empty(&mut state));
+        let mut state = self.shared.not_empty.wait_while(state, empty);
+        let n = state.queue.len().min(max);
+        if n == 0 {
+            return Err(RecvError);
+        }
+        buf.extend(state.queue.drain(..n));
+        if let Some(stats) = &self.shared.stats {
+            stats.record_recv(n);
+        }
+        // Several slots may be free now, so several senders can go
+        self.shared.notify_senders(state, n > 1);
+        Ok(n)
+    }
+
+    // Blocks for each message, ends when all senders are gone and the queue is drained
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter { receiver: self }
+    }
+
+    // Only the messages already queued, never blocks
+    pub fn try_iter(&self) -> TryIter<'_, T> {
+        TryIter { receiver: self }
+    }
+
+    // Same as recv, but waits for a message as a task instead of blocking the thread
+    pub fn recv_async(&self) -> RecvFuture<'_, T> {
+        RecvFuture { receiver: self }
+    }
+
+    pub fn len(&self) -> usize {
+        self.shared.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> Option<usize> {
+        self.shared.capacity()
+    }
+
+    pub fn stats(&self) -> Option<ChannelStats> {
+        self.shared.stats.as_ref().map(Stats::snapshot)
+    }
+
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        let mut state = self.shared.state.lock();
+        match self.shared.pop(&mut state) {
+            Some(msg) => {
+                self.shared.notify_senders(state, false);
+                Ok(msg)
+            }
+            None if state.senders == 0 => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        match deadline(timeout) {
+            Some(deadline) => self.recv_deadline(deadline),
+            None => Ok(self.recv()?),
+        }
+    }
+
+    pub fn recv_deadline(&self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        let mut state = self.shared.state.lock();
+        // A call that times out was blocked too
+        let _blocked = self
+            .shared
+            .blocked(Op::Recv, empty(&mut state) && Instant::now() < deadline);
+        loop {
+            if let Some(msg) = self.shared.pop(&mut state) {
+                self.shared.notify_senders(state, false);
+                return Ok(msg);
+            }
+            if state.senders == 0 {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            // Might wake up early (notification or spurious), the loop checks everything again
+            state = self.shared.not_empty.wait_timeout(state, deadline - now).0;
+        }
+    }
+}
+
+// Used by Select, which can't name T
+pub(crate) trait Selectable {
+    // recv wouldn't block: there is a message or the channel is disconnected
+    fn is_ready(&self) -> bool;
+    fn register(&self, thread: Thread);
+    fn unregister(&self, id: ThreadId);
+}
+
+impl<T> Selectable for Receiver<T> {
+    fn is_ready(&self) -> bool {
+        let state = self.shared.state.lock();
+        !state.queue.is_empty() || state.senders == 0
+    }
+
+    fn register(&self, thread: Thread) {
+        self.shared.state.lock().selectors.push(thread);
+    }
+
+    fn unregister(&self, id: ThreadId) {
+        self.shared.state.lock().selectors.retain(|t| t.id() != id);
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Receiver<T> {
+        self.shared.state.lock().receivers += 1;
+        Receiver {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock();
+        state.receivers -= 1;
+        if state.receivers == 0 {
+            self.shared.notify_senders(state, true);
+        }
+    }
+}
+
+impl<T> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Receiver").finish_non_exhaustive()
+    }
+}
+
+pub struct Iter<'a, T> {
+    receiver: &'a Receiver<T>,
+}
+
+impl<T> Iterator for Iter<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.receiver.recv().ok()
+    }
+}
+
+pub struct TryIter<'a, T> {
+    receiver: &'a Receiver<T>,
+}
+
+impl<T> Iterator for TryIter<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.receiver.try_recv().ok()
+    }
+}
+
+pub struct IntoIter<T> {
+    receiver: Receiver<T>,
+}
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.receiver.recv().ok()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Receiver<T> {
+    type Item = T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+impl<T> IntoIterator for Receiver<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> IntoIter<T> {
+        IntoIter { receiver: self }
+    }
+}
+
+impl<T> fmt::Debug for Iter<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Iter").finish_non_exhaustive()
+    }
+}
+
+impl<T> fmt::Debug for TryIter<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TryIter").finish_non_exhaustive()
+    }
+}
+
+impl<T> fmt::Debug for IntoIter<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IntoIter").finish_non_exhaustive()
+    }
+}
+
+pub struct RecvFuture<'a, T> {
+    receiver: &'a Receiver<T>,
+}
+
+impl<T> Future for RecvFuture<'_, T> {
+    type Output = Result<T, RecvError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let shared = &self.receiver.shared;
+        let mut state = shared.state.lock();
+        if let Some(msg) = shared.pop(&mut state) {
+            shared.notify_senders(state, false);
+            return Poll::Ready(Ok(msg));
+        }
+        if state.senders == 0 {
+            return Poll::Ready(Err(RecvError));
+        }
+        // Registered under the same lock as the check, a send can't slip in between
+        register(&mut state.recv_wakers, cx.waker());
+        Poll::Pending
+    }
+}
+
+impl<T> fmt::Debug for RecvFuture<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RecvFuture").finish_non_exhaustive()
+    }
+}
+
+pub struct SendFuture<'a, T> {
+    sender: &'a Sender<T>,
+    // Taken out when sent (or given back in the error)
+    msg: Option<T>,
+}
+
+// msg is never pinned, it is only moved in and out of the Option
+impl<T> Unpin for SendFuture<'_, T> {}
+
+impl<T> Future for SendFuture<'_, T> {
+    type Output = Result<(), SendError<T>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let shared = &this.sender.shared;
+        let mut state = shared.state.lock();
+        let msg = this.msg.take().expect("SendFuture polled after completion");
+        if state.receivers == 0 {
+            return Poll::Ready(Err(SendError(msg)));
+        }
+        if state.queue.len() < shared.capacity {
+            shared.push(&mut state, msg);
+            shared.notify_receivers(state, false);
+            return Poll::Ready(Ok(()));
+        }
+        this.msg = Some(msg);
+        register(&mut state.send_wakers, cx.waker());
+        Poll::Pending
+    }
+}
+
+impl<T> fmt::Debug for SendFuture<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SendFuture").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chapter5_build_channels::executor::block_on;
+    use crate::chapter5_build_channels::select::Select;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering::Relaxed;
+    use std::thread;
+
+    #[test]
+    fn test() {
+        let (sender, receiver) = bounded(1);
+        let t = thread::spawn(move || {
+            sender.send(1).unwrap();
+            sender.send(2).unwrap();
+        });
+        assert_eq!(receiver.recv(), Ok(1));
+        assert_eq!(receiver.recv(), Ok(2));
+        t.join().unwrap();
+    }
+
+    #[test]
+    fn test_unbounded() {
+        let (sender, receiver) = unbounded();
+        for i in 0..1000 {
+            sender.send(i).unwrap();
+        }
+        for i in 0..1000 {
+            assert_eq!(receiver.recv(), Ok(i));
+        }
+    }
+
+    #[test]
+    fn test_back_pressure() {
+        let (sender, receiver) = bounded(2);
+        let sent = AtomicUsize::new(0);
+
+        thread::scope(|s| {
+            s.spawn(|| {
+                for i in 0..3 {
+                    sender.send(i).unwrap();
+                    sent.fetch_add(1, Relaxed);
+                }
+            });
+
+            thread::sleep(Duration::from_millis(50));
+            // Queue is full, third send must wait for a receive
+            assert_eq!(sent.load(Relaxed), 2);
+
+            assert_eq!(receiver.recv(), Ok(0));
+        });
+
+        assert_eq!(sent.load(Relaxed), 3);
+        assert_eq!(receiver.recv(), Ok(1));
+        assert_eq!(receiver.recv(), Ok(2));
+    }
+
+    #[test]
+    fn test_multi_producer_multi_consumer() {
+        const PRODUCERS: usize = 4;
+        const CONSUMERS: usize = 4;
+        const MESSAGES: usize = 1000;
+
+        let (sender, receiver) = bounded(8);
+        let sum = AtomicUsize::new(0);
+
+        thread::scope(|s| {
+            for p in 0..PRODUCERS {
+                let sender = sender.clone();
+                s.spawn(move || {
+                    for i in 0..MESSAGES {
+                        sender.send(p * MESSAGES + i).unwrap();
+                    }
+                });
+            }
+            drop(sender);
+            for _ in 0..CONSUMERS {
+                let receiver = receiver.clone();
+                let sum = &sum;
+                // Consumers don't need to know how many messages there are, they stop on disconnection
+                s.spawn(move || {
+                    while let Ok(msg) = receiver.recv() {
+                        sum.fetch_add(msg, Relaxed);
+                    }
+                });
+            }
+        });
+
+        let n = PRODUCERS * MESSAGES;
+        assert_eq!(sum.load(Relaxed), n * (n - 1) / 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "capacity must be greater than zero")]
+    fn test_zero_capacity() {
+        bounded::<i32>(0);
+    }
+
+    #[test]
+    fn test_try_recv() {
+        let (sender, receiver) = bounded(1);
+        assert_eq!(receiver.try_recv(), Err(TryRecvError::Empty));
+        sender.send(1).unwrap();
+        assert_eq!(receiver.try_recv(), Ok(1));
+        sender.send(2).unwrap();
+        drop(sender);
+        assert_eq!(receiver.try_recv(), Ok(2));
+        assert_eq!(receiver.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn test_recv_timeout() {
+        let (sender, receiver) = bounded(1);
+
+        let start = Instant::now();
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_millis(50)),
+            Err(RecvTimeoutError::Timeout)
+        );
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert_eq!(
+            receiver.recv_deadline(Instant::now()),
+            Err(RecvTimeoutError::Timeout)
+        );
+
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(20));
+                sender.send(1).unwrap();
+            });
+            assert_eq!(receiver.recv_timeout(Duration::from_secs(10)), Ok(1));
+        });
+
+        // Already queued message is returned even if the deadline is passed
+        sender.send(2).unwrap();
+        assert_eq!(receiver.recv_deadline(Instant::now()), Ok(2));
+        sender.send(3).unwrap();
+        assert_eq!(receiver.recv_timeout(Duration::MAX), Ok(3));
+
+        thread::scope(|s| {
+            s.spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                drop(sender);
+            });
+            // Disconnection is reported right away, without waiting for the timeout
+            let start = Instant::now();
+            assert_eq!(
+                receiver.recv_timeout(Duration::from_secs(10)),
+                Err(RecvTimeoutError::Disconnected)
+            );
+            assert!(start.elapsed() < Duration::from_secs(10));
+        });
+    }
+
+    #[test]
+    fn test_senders_dropped_first() {
+        let (sender, receiver) = bounded(4);
+        let sender2 = sender.clone();
+        sender.send(1).unwrap();
+        sender2.send(2).unwrap();
+        drop(sender);
+        // One sender left, still connected
+        sender2.send(3).unwrap();
+        drop(sender2);
+
+        // Queued messages are drained before reporting the disconnection
+        assert_eq!(receiver.recv(), Ok(1));
+        assert_eq!(receiver.recv(), Ok(2));
+        assert_eq!(receiver.recv(), Ok(3));
+        assert_eq!(receiver.recv(), Err(RecvError));
+    }
+
+    #[test]
+    fn test_receivers_dropped_first() {
+        let (sender, receiver) = bounded(4);
+        let receiver2 = receiver.clone();
+        drop(receiver);
+        sender.send(1).unwrap();
+        drop(receiver2);
+
+        assert_eq!(sender.send(2), Err(SendError(2)));
+        assert_eq!(sender.send(3).unwrap_err().into_inner(), 3);
+    }
+
+    #[test]
+    fn test_blocked_receiver_wakes_on_disconnect() {
+        let (sender, receiver) = bounded::<i32>(1);
+        thread::scope(|s| {
+            let t = s.spawn(move || receiver.recv());
+            thread::sleep(Duration::from_millis(50));
+            drop(sender);
+            assert_eq!(t.join().unwrap(), Err(RecvError));
+        });
+    }
+
+    #[test]
+    fn test_blocked_sender_wakes_on_disconnect() {
+        let (sender, receiver) = bounded(1);
+        sender.send(1).unwrap();
+        thread::scope(|s| {
+            // Queue is full, so this send blocks until the receiver is gone
+            let t = s.spawn(move || sender.send(2));
+            thread::sleep(Duration::from_millis(50));
+            drop(receiver);
+            assert_eq!(t.join().unwrap(), Err(SendError(2)));
+        });
+    }
+
+    #[test]
+    fn test_select_unregisters() {
+        let (_sender, receiver) = bounded::<i32>(1);
+        let select = Select::new().recv(&receiver);
+        assert_eq!(select.ready_timeout(Duration::from_millis(1)), None);
+        // Selecting thread is gone from the channel once select returns
+        assert!(receiver.shared.state.lock().selectors.is_empty());
+    }
+
+    #[test]
+    fn test_recv_async() {
+        let (sender, receiver) = bounded(1);
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(20));
+                sender.send(1).unwrap();
+            });
+            assert_eq!(block_on(receiver.recv_async()), Ok(1));
+        });
+        drop(sender);
+        assert_eq!(block_on(receiver.recv_async()), Err(RecvError));
+    }
+
+    #[test]
+    fn test_send_async() {
+        let (sender, receiver) = bounded(1);
+        block_on(sender.send_async(1)).unwrap();
+        thread::scope(|s| {
+            // Full, the task waits until the blocking receiver makes room
+            let t = s.spawn(|| block_on(sender.send_async(2)));
+            thread::sleep(Duration::from_millis(20));
+            assert!(!t.is_finished());
+            assert_eq!(receiver.recv(), Ok(1));
+            assert_eq!(t.join().unwrap(), Ok(()));
+        });
+        assert_eq!(receiver.recv(), Ok(2));
+        drop(receiver);
+        assert_eq!(block_on(sender.send_async(3)), Err(SendError(3)));
+    }
+
+    #[test]
+    fn test_async_and_blocking_mixed() {
+        const MESSAGES: usize = 1000;
+
+        let (sender, receiver) = bounded(2);
+        let sum = AtomicUsize::new(0);
+        thread::scope(|s| {
+            let async_sender = sender.clone();
+            s.spawn(move || {
+                block_on(async {
+                    for i in 0..MESSAGES {
+                        async_sender.send_async(i).await.unwrap();
+                    }
+                })
+            });
+            s.spawn(move || {
+                for i in MESSAGES..2 * MESSAGES {
+                    sender.send(i).unwrap();
+                }
+            });
+            let async_receiver = receiver.clone();
+            let sum = &sum;
+            s.spawn(move || {
+                block_on(async {
+                    while let Ok(msg) = async_receiver.recv_async().await {
+                        sum.fetch_add(msg, Relaxed);
+                    }
+                })
+            });
+            while let Ok(msg) = receiver.recv() {
+                sum.fetch_add(msg, Relaxed);
+            }
+        });
+
+        let n = 2 * MESSAGES;
+        assert_eq!(sum.load(Relaxed), n * (n - 1) / 2);
+    }
+
+    #[test]
+    fn test_iter() {
+        let (sender, receiver) = bounded(2);
+        thread::scope(|s| {
+            s.spawn(move || {
+                for i in 0..10 {
+                    sender.send(i).unwrap();
+                }
+            });
+            // Ends once the sender is dropped and everything is received
+            assert_eq!(
+                receiver.iter().collect::<Vec<_>>(),
+                (0..10).collect::<Vec<_>>()
+            );
+        });
+
+        let (sender, receiver) = unbounded();
+        sender.send(1).unwrap();
+        drop(sender);
+        assert_eq!(receiver.into_iter().collect::<Vec<_>>(), [1]);
+    }
+
+    #[test]
+    fn test_try_iter() {
+        let (sender, receiver) = unbounded();
+        for i in 0..3 {
+            sender.send(i).unwrap();
+        }
+        // Still connected, but stops as soon as the queue is empty
+        assert_eq!(receiver.try_iter().collect::<Vec<_>>(), [0, 1, 2]);
+        assert_eq!(receiver.try_iter().next(), None);
+    }
+
+    #[test]
+    fn test_recv_many() {
+        let (sender, receiver) = unbounded();
+        for i in 0..5 {
+            sender.send(i).unwrap();
+        }
+        let mut buf = vec![-1];
+        assert_eq!(receiver.recv_many(&mut buf, 3), Ok(3));
+        assert_eq!(buf, [-1, 0, 1, 2]);
+        assert_eq!(receiver.recv_many(&mut buf, 0), Ok(0));
+        assert_eq!(receiver.recv_many(&mut buf, 10), Ok(2));
+        assert_eq!(buf, [-1, 0, 1, 2, 3, 4]);
+
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(20));
+                sender.send(5).unwrap();
+            });
+            // Blocks until there is at least one message
+            assert_eq!(receiver.recv_many(&mut buf, 10), Ok(1));
+        });
+        drop(sender);
+        assert_eq!(receiver.recv_many(&mut buf, 10), Err(RecvError));
+        assert_eq!(buf.len(), 7);
+    }
+
+    #[test]
+    fn test_recv_many_wakes_senders() {
+        let (sender, receiver) = bounded(4);
+        thread::scope(|s| {
+            for t in 0..4 {
+                let sender = sender.clone();
+                s.spawn(move || {
+                    for i in 0..100 {
+                        sender.send(t * 100 + i).unwrap();
+                    }
+                });
+            }
+            drop(sender);
+            let mut buf = Vec::new();
+            while receiver.recv_many(&mut buf, 4).is_ok() {}
+            buf.sort();
+            assert_eq!(buf, (0..400).collect::<Vec<_>>());
+        });
+    }
+
+    #[test]
+    fn test_len_and_capacity() {
+        let (sender, receiver) = bounded(2);
+        assert_eq!(sender.capacity(), Some(2));
+        assert!(receiver.is_empty());
+        sender.send(1).unwrap();
+        assert_eq!((sender.len(), receiver.len()), (1, 1));
+        assert_eq!(receiver.stats(), None);
+
+        let (sender, _receiver) = unbounded::<i32>();
+        assert_eq!(sender.capacity(), None);
+    }
+
+    #[test]
+    fn test_stats() {
+        let (sender, receiver) = bounded_with_stats(2);
+        sender.send(1).unwrap();
+        sender.send(2).unwrap();
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(20));
+                assert_eq!(receiver.recv(), Ok(1));
+            });
+            // Full, waits for the receiver
+            sender.send(3).unwrap();
+        });
+        let mut buf = Vec::new();
+        assert_eq!(receiver.recv_many(&mut buf, 10), Ok(2));
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_millis(20)),
+            Err(RecvTimeoutError::Timeout)
+        );
+
+        let stats = receiver.stats().unwrap();
+        assert_eq!(sender.stats(), Some(stats));
+        assert_eq!((stats.sent, stats.received, stats.max_depth), (3, 3, 2));
+        assert_eq!((stats.blocked_sends, stats.blocked_recvs), (1, 1));
+        assert!(stats.wait_time >= Duration::from_millis(20));
+    }
+}
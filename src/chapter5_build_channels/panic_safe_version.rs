@@ -13,7 +13,7 @@ struct Channcel<T> {
     state: AtomicU8,
 }
 
-unsafe impl<T> Sync for Channcel<T> {}
+unsafe impl<T: Send> Sync for Channcel<T> {}
 
 impl<T> Channcel<T> {
     pub const fn new() -> Channcel<T> {
@@ -0,0 +1,324 @@
+use crate::chapter5_build_channels::deadline::deadline;
+use crate::chapter5_build_channels::error::{RecvError, RecvTimeoutError, SendError, TryRecvError};
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::fmt;
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+// Same as simple_version::Channel, but the queue is a binary heap instead of a VecDeque:
+// the message with the highest priority is received first, whenever it was sent
+// Messages with the same priority keep their send order (FIFO), BinaryHeap alone doesn't do that,
+// so every message gets a sequence number and the older one wins a tie
+struct Entry<P, T> {
+    priority: P,
+    seq: u64,
+    msg: T,
+}
+
+impl<P: Ord, T> Ord for Entry<P, T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Max-heap: higher priority first, then lower seq (sent earlier) first
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+impl<P: Ord, T> PartialOrd for Entry<P, T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<P: Ord, T> PartialEq for Entry<P, T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<P: Ord, T> Eq for Entry<P, T> {}
+
+struct Queue<P, T> {
+    heap: BinaryHeap<Entry<P, T>>,
+    // Sequence number of the next message, u64 won't wrap around in practice
+    next_seq: u64,
+    // Same as simple_version::Channel, kept under the heap lock
+    closed: bool,
+}
+
+pub struct PriorityChannel<P, T> {
+    queue: Mutex<Queue<P, T>>,
+    ready: Condvar,
+}
+
+impl<P: Ord, T> PriorityChannel<P, T> {
+    pub const fn new() -> PriorityChannel<P, T> {
+        PriorityChannel {
+            queue: Mutex::new(Queue {
+                heap: BinaryHeap::new(),
+                next_seq: 0,
+                closed: false,
+            }),
+            ready: Condvar::new(),
+        }
+    }
+
+    // Bigger priority is received first
+    // Fail only once the channel is closed, the message is given back in the error
+    pub fn send(&self, priority: P, msg: T) -> Result<(), SendError<T>> {
+        let mut queue = self.queue.lock().unwrap();
+        if queue.closed {
+            return Err(SendError(msg));
+        }
+        let seq = queue.next_seq;
+        queue.next_seq += 1;
+        queue.heap.push(Entry { priority, seq, msg });
+        drop(queue);
+        self.ready.notify_one();
+        Ok(())
+    }
+
+    // RecvError once the channel is closed and every queued message has been received
+    pub fn receive(&self) -> Result<T, RecvError> {
+        let mut guard = self.queue.lock().unwrap();
+        loop {
+            if let Some(entry) = guard.heap.pop() {
+                return Ok(entry.msg);
+            }
+            if guard.closed {
+                return Err(RecvError);
+            }
+            guard = self.ready.wait(guard).unwrap();
+        }
+    }
+
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        let mut guard = self.queue.lock().unwrap();
+        if let Some(entry) = guard.heap.pop() {
+            return Ok(entry.msg);
+        }
+        if guard.closed {
+            return Err(TryRecvError::Disconnected);
+        }
+        Err(TryRecvError::Empty)
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        match deadline(timeout) {
+            Some(deadline) => self.recv_deadline(deadline),
+            None => self.receive().map_err(RecvTimeoutError::from),
+        }
+    }
+
+    pub fn recv_deadline(&self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        let mut guard = self.queue.lock().unwrap();
+        loop {
+            if let Some(entry) = guard.heap.pop() {
+                return Ok(entry.msg);
+            }
+            if guard.closed {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            guard = self.ready.wait_timeout(guard, deadline - now).unwrap().0;
+        }
+    }
+
+    // Disconnect both sides, every waiting receiver wakes up
+    // Closing twice does nothing
+    pub fn close(&self) {
+        self.queue.lock().unwrap().closed = true;
+        self.ready.notify_all();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.queue.lock().unwrap().closed
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.lock().unwrap().heap.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<P: Ord, T> Default for PriorityChannel<P, T> {
+    fn default() -> PriorityChannel<P, T> {
+        PriorityChannel::new()
+    }
+}
+
+impl<P, T> fmt::Debug for PriorityChannel<P, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PriorityChannel").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cmp::Reverse;
+    use std::thread;
+
+    #[test]
+    fn test() {
+        let channel = PriorityChannel::new();
+        channel.send(1, "data 1").unwrap();
+        channel.send(1, "data 2").unwrap();
+        channel.send(10, "control 1").unwrap();
+        channel.send(1, "data 3").unwrap();
+        channel.send(10, "control 2").unwrap();
+        assert_eq!(channel.len(), 5);
+
+        // Control messages jump the queue, equal priorities keep their send order
+        let received: Vec<_> = (0..5).map(|_| channel.receive().unwrap()).collect();
+        assert_eq!(
+            received,
+            ["control 1", "control 2", "data 1", "data 2", "data 3"]
+        );
+        assert!(channel.is_empty());
+    }
+
+    #[test]
+    fn test_lowest_first() {
+        // Reverse turns it around, e.g. for deadlines
+        let channel = PriorityChannel::new();
+        for i in [3, 1, 2] {
+            channel.send(Reverse(i), i).unwrap();
+        }
+        assert_eq!(channel.try_recv(), Ok(1));
+        assert_eq!(channel.try_recv(), Ok(2));
+        assert_eq!(channel.try_recv(), Ok(3));
+    }
+
+    #[test]
+    fn test_try_recv_and_timeout() {
+        let channel = PriorityChannel::<u8, i32>::new();
+        assert_eq!(channel.try_recv(), Err(TryRecvError::Empty));
+        assert_eq!(
+            channel.recv_timeout(Duration::from_millis(20)),
+            Err(RecvTimeoutError::Timeout)
+        );
+
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(20));
+                channel.send(0, 1).unwrap();
+            });
+            assert_eq!(channel.recv_timeout(Duration::from_secs(10)), Ok(1));
+        });
+
+        channel.send(0, 2).unwrap();
+        assert_eq!(channel.recv_deadline(Instant::now()), Ok(2));
+        channel.send(0, 3).unwrap();
+        assert_eq!(channel.recv_timeout(Duration::MAX), Ok(3));
+    }
+
+    #[test]
+    fn test_between_threads() {
+        const SENDERS: usize = 4;
+        const MESSAGES: usize = 500;
+
+        let channel = PriorityChannel::new();
+        thread::scope(|s| {
+            for t in 0..SENDERS {
+                let channel = &channel;
+                s.spawn(move || {
+                    for i in 0..MESSAGES {
+                        channel.send(i % 3, (t, i)).unwrap();
+                    }
+                });
+            }
+        });
+
+        // Everything is queued: priorities come out in order, and within a priority
+        // each sender's messages in the order that sender sent them
+        let received: Vec<(usize, usize)> = (0..SENDERS * MESSAGES)
+            .map(|_| channel.receive().unwrap())
+            .collect();
+        assert!(received.windows(2).all(|w| w[0].1 % 3 >= w[1].1 % 3));
+        for t in 0..SENDERS {
+            for p in 0..3 {
+                let mine: Vec<usize> = received
+                    .iter()
+                    .filter(|&&(t2, i)| t2 == t && i % 3 == p)
+                    .map(|&(_, i)| i)
+                    .collect();
+                assert!(mine.windows(2).all(|w| w[0] < w[1]));
+                assert_eq!(mine.len(), (p..MESSAGES).step_by(3).count());
+            }
+        }
+        assert!(channel.is_empty());
+    }
+
+    #[test]
+    fn test_close_after_send() {
+        let channel = PriorityChannel::new();
+        channel.send(1, "data").unwrap();
+        channel.send(10, "control").unwrap();
+        channel.close();
+        assert!(channel.is_closed());
+        // Messages sent before close are still delivered, by priority
+        assert_eq!(channel.receive(), Ok("control"));
+        assert_eq!(channel.try_recv(), Ok("data"));
+        assert_eq!(channel.receive(), Err(RecvError));
+        assert_eq!(channel.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn test_close_while_receiving() {
+        let channel = PriorityChannel::new();
+        thread::scope(|s| {
+            let receivers: Vec<_> = (0..2).map(|_| s.spawn(|| channel.receive())).collect();
+            // Producer is done after one message, it closes the channel on its way out
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(20));
+                channel.send(0, 1).unwrap();
+                channel.close();
+            });
+            // notify_all, the receiver that didn't get the message isn't left blocked
+            let results: Vec<_> = receivers.into_iter().map(|r| r.join().unwrap()).collect();
+            assert!(results.contains(&Ok(1)));
+            assert!(results.contains(&Err(RecvError)));
+        });
+    }
+
+    #[test]
+    fn test_close_before_send() {
+        let channel = PriorityChannel::new();
+        // Receiver side is gone first, nobody would ever receive the message
+        channel.close();
+        assert_eq!(channel.send(0, "hello"), Err(SendError("hello")));
+        assert!(channel.is_empty());
+        // Closing again changes nothing
+        channel.close();
+        assert_eq!(channel.receive(), Err(RecvError));
+    }
+
+    #[test]
+    fn test_close_while_waiting_with_timeout() {
+        let channel = PriorityChannel::<u8, i32>::new();
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(20));
+                channel.close();
+            });
+            let start = Instant::now();
+            assert_eq!(
+                channel.recv_deadline(start + Duration::from_secs(10)),
+                Err(RecvTimeoutError::Disconnected)
+            );
+            assert!(start.elapsed() < Duration::from_secs(10));
+        });
+        assert_eq!(
+            channel.recv_timeout(Duration::MAX),
+            Err(RecvTimeoutError::Disconnected)
+        );
+    }
+}
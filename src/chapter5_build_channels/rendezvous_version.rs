@@ -0,0 +1,356 @@
+use crate::chapter5_build_channels::deadline::{deadline, park_until};
+use crate::chapter5_build_channels::error::{RecvError, RecvTimeoutError, SendError, TryRecvError};
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::Mutex;
+use std::thread::{self, Thread};
+use std::time::{Duration, Instant};
+
+// Zero capacity channel: a message is handed directly from a sender to a receiver
+// send blocks until a receiver took the message, receive blocks until a sender offers one
+// Only one message is on offer at a time, other senders wait for their turn
+// Waiting threads park instead of waiting on a condvar, the lock only protects the bookkeeping
+// Like simple_version::Channel there are no handles, close disconnects both sides: every waiting
+// thread is woken up, and a message on offer that no receiver took yet goes back to its sender
+struct State<T> {
+    // Message on offer by the current sender
+    msg: Option<T>,
+    // Set by the receiver that took msg, tells the current sender it can return
+    taken: bool,
+    // Sender whose message is on offer (or was just taken)
+    sender: Option<Thread>,
+    waiting_senders: VecDeque<Thread>,
+    waiting_receivers: VecDeque<Thread>,
+    closed: bool,
+}
+
+pub struct RendezvousChannel<T> {
+    state: Mutex<State<T>>,
+}
+
+// Add the current thread to a wait list, once
+fn enqueue(queue: &mut VecDeque<Thread>) {
+    let id = thread::current().id();
+    if !queue.iter().any(|t| t.id() == id) {
+        queue.push_back(thread::current());
+    }
+}
+
+fn dequeue(queue: &mut VecDeque<Thread>) {
+    let id = thread::current().id();
+    queue.retain(|t| t.id() != id);
+}
+
+impl<T> RendezvousChannel<T> {
+    pub const fn new() -> RendezvousChannel<T> {
+        RendezvousChannel {
+            state: Mutex::new(State {
+                msg: None,
+                taken: false,
+                sender: None,
+                waiting_senders: VecDeque::new(),
+                waiting_receivers: VecDeque::new(),
+                closed: false,
+            }),
+        }
+    }
+
+    // Returns only once a receiver has the message
+    // Fails once the channel is closed before a receiver took it, the message is given back
+    pub fn send(&self, msg: T) -> Result<(), SendError<T>> {
+        let mut state = self.state.lock().unwrap();
+        // Wait for our turn, another sender's message is on offer
+        while state.sender.is_some() && !state.closed {
+            enqueue(&mut state.waiting_senders);
+            drop(state);
+            // Every wait list is checked again after waking up, so spurious wakeups are fine
+            thread::park();
+            state = self.state.lock().unwrap();
+        }
+        dequeue(&mut state.waiting_senders);
+        if state.closed {
+            return Err(SendError(msg));
+        }
+
+        state.sender = Some(thread::current());
+        state.msg = Some(msg);
+        if let Some(receiver) = state.waiting_receivers.front() {
+            receiver.unpark();
+        }
+
+        while !state.taken && !state.closed {
+            drop(state);
+            thread::park();
+            state = self.state.lock().unwrap();
+        }
+        // Closed while on offer: nobody took it, so it's still there to give back
+        let result = if state.taken {
+            Ok(())
+        } else {
+            Err(SendError(state.msg.take().unwrap()))
+        };
+        state.taken = false;
+        state.sender = None;
+        if let Some(sender) = state.waiting_senders.front() {
+            sender.unpark();
+        }
+        result
+    }
+
+    // RecvError once the channel is closed and no message is on offer
+    pub fn receive(&self) -> Result<T, RecvError> {
+        self.wait_until(None).map_err(|_| RecvError)
+    }
+
+    // Only succeeds if a sender is blocked in send right now
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(msg) = Self::take(&mut state) {
+            return Ok(msg);
+        }
+        if state.closed {
+            return Err(TryRecvError::Disconnected);
+        }
+        Err(TryRecvError::Empty)
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        self.wait_until(deadline(timeout))
+    }
+
+    pub fn recv_deadline(&self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        self.wait_until(Some(deadline))
+    }
+
+    // Disconnect both sides, every waiting sender and receiver wakes up
+    // Closing twice does nothing
+    pub fn close(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.closed = true;
+        let waiting = state.waiting_senders.iter().chain(&state.waiting_receivers);
+        for t in waiting.chain(&state.sender) {
+            t.unpark();
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.state.lock().unwrap().closed
+    }
+
+    fn wait_until(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        let mut state = self.state.lock().unwrap();
+        let result = loop {
+            if let Some(msg) = Self::take(&mut state) {
+                return Ok(msg);
+            }
+            if state.closed {
+                break Err(RecvTimeoutError::Disconnected);
+            }
+            // Registered under the lock, so an unpark from a sender can't be missed:
+            // if it comes before park, park returns right away
+            enqueue(&mut state.waiting_receivers);
+            drop(state);
+            let parked = park_until(deadline);
+            state = self.state.lock().unwrap();
+            if !parked {
+                // One last look, a sender could have offered right at the deadline
+                if let Some(msg) = Self::take(&mut state) {
+                    return Ok(msg);
+                }
+                break Err(RecvTimeoutError::Timeout);
+            }
+        };
+        // Leaving without a message, a sender shouldn't wake us up for nothing
+        dequeue(&mut state.waiting_receivers);
+        result
+    }
+
+    fn take(state: &mut State<T>) -> Option<T> {
+        let msg = state.msg.take()?;
+        state.taken = true;
+        dequeue(&mut state.waiting_receivers);
+        state.sender.as_ref().unwrap().unpark();
+        Some(msg)
+    }
+}
+
+impl<T> Default for RendezvousChannel<T> {
+    fn default() -> RendezvousChannel<T> {
+        RendezvousChannel::new()
+    }
+}
+
+impl<T> fmt::Debug for RendezvousChannel<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RendezvousChannel").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::Ordering::Relaxed;
+    use std::sync::atomic::{AtomicBool, AtomicUsize};
+
+    #[test]
+    fn test() {
+        let channel = RendezvousChannel::new();
+        thread::scope(|s| {
+            s.spawn(|| channel.send("hello").unwrap());
+            assert_eq!(channel.receive(), Ok("hello"));
+        });
+    }
+
+    #[test]
+    fn test_send_waits_for_receive() {
+        let channel = RendezvousChannel::new();
+        let sent = AtomicBool::new(false);
+        thread::scope(|s| {
+            let t = s.spawn(|| {
+                channel.send(1).unwrap();
+                sent.store(true, Relaxed);
+            });
+            thread::sleep(Duration::from_millis(50));
+            // Nobody received yet, so send is still blocked
+            assert!(!sent.load(Relaxed));
+            assert_eq!(channel.receive(), Ok(1));
+            t.join().unwrap();
+            assert!(sent.load(Relaxed));
+        });
+    }
+
+    #[test]
+    fn test_receive_waits_for_send() {
+        let channel = RendezvousChannel::new();
+        let received = AtomicBool::new(false);
+        thread::scope(|s| {
+            s.spawn(|| {
+                assert_eq!(channel.receive(), Ok(1));
+                received.store(true, Relaxed);
+            });
+            thread::sleep(Duration::from_millis(50));
+            assert!(!received.load(Relaxed));
+            channel.send(1).unwrap();
+        });
+        assert!(received.load(Relaxed));
+    }
+
+    #[test]
+    fn test_try_recv() {
+        let channel = RendezvousChannel::new();
+        // Zero capacity, nothing can be waiting without a blocked sender
+        assert_eq!(channel.try_recv(), Err(TryRecvError::Empty));
+        thread::scope(|s| {
+            s.spawn(|| channel.send(1).unwrap());
+            loop {
+                match channel.try_recv() {
+                    Ok(msg) => break assert_eq!(msg, 1),
+                    Err(_) => thread::sleep(Duration::from_millis(1)),
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn test_many_senders_and_receivers() {
+        const THREADS: usize = 4;
+        const MESSAGES: usize = 500;
+
+        let channel = RendezvousChannel::new();
+        let sent = AtomicUsize::new(0);
+        let sum = AtomicUsize::new(0);
+        thread::scope(|s| {
+            for t in 0..THREADS {
+                let (channel, sent) = (&channel, &sent);
+                s.spawn(move || {
+                    for i in 0..MESSAGES {
+                        channel.send(t * MESSAGES + i).unwrap();
+                        sent.fetch_add(1, Relaxed);
+                    }
+                });
+            }
+            for _ in 0..THREADS {
+                s.spawn(|| {
+                    for _ in 0..MESSAGES {
+                        sum.fetch_add(channel.receive().unwrap(), Relaxed);
+                    }
+                });
+            }
+        });
+
+        let n = THREADS * MESSAGES;
+        assert_eq!(sent.load(Relaxed), n);
+        assert_eq!(sum.load(Relaxed), n * (n - 1) / 2);
+    }
+
+    #[test]
+    fn test_close_while_sending() {
+        let channel = RendezvousChannel::new();
+        thread::scope(|s| {
+            // First one is on offer, the second one waits for its turn
+            let senders: Vec<_> = (0..2)
+                .map(|i| {
+                    let channel = &channel;
+                    s.spawn(move || channel.send(i))
+                })
+                .collect();
+            thread::sleep(Duration::from_millis(50));
+            channel.close();
+            // No receiver came, both messages are given back
+            let mut msgs: Vec<_> = senders
+                .into_iter()
+                .map(|t| t.join().unwrap().unwrap_err().into_inner())
+                .collect();
+            msgs.sort();
+            assert_eq!(msgs, [0, 1]);
+        });
+        assert_eq!(channel.send(2), Err(SendError(2)));
+    }
+
+    #[test]
+    fn test_close_while_receiving() {
+        let channel = RendezvousChannel::<i32>::new();
+        thread::scope(|s| {
+            let receivers: Vec<_> = (0..2).map(|_| s.spawn(|| channel.receive())).collect();
+            thread::sleep(Duration::from_millis(20));
+            channel.close();
+            for r in receivers {
+                assert_eq!(r.join().unwrap(), Err(RecvError));
+            }
+        });
+        assert!(channel.is_closed());
+        assert_eq!(channel.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn test_recv_timeout() {
+        let channel = RendezvousChannel::new();
+        let start = Instant::now();
+        assert_eq!(
+            channel.recv_timeout(Duration::from_millis(20)),
+            Err(RecvTimeoutError::Timeout)
+        );
+        assert!(start.elapsed() >= Duration::from_millis(20));
+
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(20));
+                channel.send(1).unwrap();
+            });
+            assert_eq!(channel.recv_timeout(Duration::from_secs(10)), Ok(1));
+
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(20));
+                channel.close();
+            });
+            let start = Instant::now();
+            assert_eq!(
+                channel.recv_deadline(start + Duration::from_secs(10)),
+                Err(RecvTimeoutError::Disconnected)
+            );
+            assert!(start.elapsed() < Duration::from_secs(10));
+        });
+        // Receivers that gave up took themselves off the wait list
+        assert!(channel.state.lock().unwrap().waiting_receivers.is_empty());
+    }
+}
@@ -0,0 +1,489 @@
+use crate::chapter5_build_channels::deadline::deadline;
+use crate::chapter5_build_channels::error::{RecvError, RecvTimeoutError, SendError, TryRecvError};
+use crate::chapter6_build_arc::weak_pointer::Arc;
+use crate::chapter8_os_primitives::futex;
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::mem::MaybeUninit;
+use std::sync::atomic::Ordering::{Acquire, Relaxed, Release, SeqCst};
+use std::sync::atomic::{AtomicU32, AtomicUsize};
+use std::time::{Duration, Instant};
+
+// Bounded MPMC channel without a mutex (Dmitry Vyukov's bounded MPMC queue)
+// Every slot has a sequence number that tells which lap of the ring it's ready for:
+// - seq == 2 * pos:     slot is empty, a sender at position pos can write it
+// - seq == 2 * pos + 1: slot holds the message written at pos, a receiver at pos can read it
+// After reading, seq becomes 2 * (pos + capacity), for the sender of the next lap
+// (Vyukov uses pos and pos + 1, but then a full slot looks empty when the capacity is 1)
+// Senders and receivers only race on their own position counter (one CAS), the slot itself
+// is owned by whoever won that position
+// Threads only sleep (futex) when the ring is full or empty
+struct Slot<T> {
+    seq: AtomicUsize,
+    msg: UnsafeCell<MaybeUninit<T>>,
+}
+
+struct Shared<T> {
+    slots: Box<[Slot<T>]>,
+    // Next position to receive from and to send to, only ever increase
+    // Positions are taken modulo the capacity, they won't wrap around usize in practice
+    head: AtomicUsize,
+    tail: AtomicUsize,
+    senders: AtomicUsize,
+    receivers: AtomicUsize,
+    // Futex words, bumped after every push/pop so a sleeping thread can't miss it
+    pushed: AtomicU32,
+    popped: AtomicU32,
+    // Skip the wake syscall when nobody sleeps
+    recv_waiters: AtomicUsize,
+    send_waiters: AtomicUsize,
+}
+
+unsafe impl<T: Send> Sync for Shared<T> {}
+
+impl<T> Shared<T> {
+    fn try_push(&self, msg: T) -> Result<(), T> {
+        let capacity = self.slots.len();
+        let mut pos = self.tail.load(Relaxed);
+        loop {
+            let slot = &self.slots[pos % capacity];
+            // Acquire pairs with the receiver's Release, it's done reading the old message
+            let seq = slot.seq.load(Acquire);
+            let diff = seq.wrapping_sub(pos.wrapping_mul(2)) as isize;
+            if diff == 0 {
+                match self
+                    .tail
+                    .compare_exchange_weak(pos, pos.wrapping_add(1), Relaxed, Relaxed)
+                {
+                    Ok(_) => {
+                        unsafe { (*slot.msg.get()).write(msg) };
+                        slot.seq.store(pos.wrapping_mul(2).wrapping_add(1), Release);
+                        return Ok(());
+                    }
+                    Err(current) => pos = current,
+                }
+            } else if diff < 0 {
+                // Slot still holds the message of the previous lap: full
+                return Err(msg);
+            } else {
+                // Another sender took this position already
+                pos = self.tail.load(Relaxed);
+            }
+        }
+    }
+
+    fn try_pop(&self) -> Option<T> {
+        let capacity = self.slots.len();
+        let mut pos = self.head.load(Relaxed);
+        loop {
+            let slot = &self.slots[pos % capacity];
+            // Acquire pairs with the sender's Release, the message is fully written
+            let seq = slot.seq.load(Acquire);
+            let diff = seq.wrapping_sub(pos.wrapping_mul(2).wrapping_add(1)) as isize;
+            if diff == 0 {
+                match self
+                    .head
+                    .compare_exchange_weak(pos, pos.wrapping_add(1), Relaxed, Relaxed)
+                {
+                    Ok(_) => {
+                        let msg = unsafe { (*slot.msg.get()).assume_init_read() };
+                        slot.seq
+                            .store(pos.wrapping_add(capacity).wrapping_mul(2), Release);
+                        return Some(msg);
+                    }
+                    Err(current) => pos = current,
+                }
+            } else if diff < 0 {
+                // Nothing written at this position yet: empty
+                return None;
+            } else {
+                pos = self.head.load(Relaxed);
+            }
+        }
+    }
+
+    // The other side loads the futex word before checking the ring and registers as a waiter
+    // before sleeping, so either it sees our change or we see it waiting (all SeqCst)
+    fn notify(epoch: &AtomicU32, waiters: &AtomicUsize) {
+        epoch.fetch_add(1, SeqCst);
+        if waiters.load(SeqCst) > 0 {
+            futex::wake_one(epoch);
+        }
+    }
+
+    fn disconnect(epoch: &AtomicU32) {
+        epoch.fetch_add(1, SeqCst);
+        futex::wake_all(epoch);
+    }
+}
+
+impl<T> Drop for Shared<T> {
+    fn drop(&mut self) {
+        // Both sides are gone, drop the messages nobody received
+        let capacity = self.slots.len();
+        let tail = *self.tail.get_mut();
+        let mut pos = *self.head.get_mut();
+        while pos != tail {
+            unsafe { self.slots[pos % capacity].msg.get_mut().assume_init_drop() };
+            pos = pos.wrapping_add(1);
+        }
+    }
+}
+
+pub fn bounded<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "capacity must be greater than zero");
+    let slots = (0..capacity)
+        .map(|i| Slot {
+            seq: AtomicUsize::new(i * 2),
+            msg: UnsafeCell::new(MaybeUninit::uninit()),
+        })
+        .collect();
+    let shared = Arc::new(Shared {
+        slots,
+        head: AtomicUsize::new(0),
+        tail: AtomicUsize::new(0),
+        senders: AtomicUsize::new(1),
+        receivers: AtomicUsize::new(1),
+        pushed: AtomicU32::new(0),
+        popped: AtomicU32::new(0),
+        recv_waiters: AtomicUsize::new(0),
+        send_waiters: AtomicUsize::new(0),
+    });
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared },
+    )
+}
+
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    pub fn send(&self, mut msg: T) -> Result<(), SendError<T>> {
+        let shared = &self.shared;
+        loop {
+            let epoch = shared.popped.load(SeqCst);
+            if shared.receivers.load(SeqCst) == 0 {
+                return Err(SendError(msg));
+            }
+            match shared.try_push(msg) {
+                Ok(()) => {
+                    Shared::<T>::notify(&shared.pushed, &shared.recv_waiters);
+                    return Ok(());
+                }
+                Err(m) => msg = m,
+            }
+            // Full, sleep until a receiver makes room (or the last one is dropped)
+            shared.send_waiters.fetch_add(1, SeqCst);
+            futex::wait(&shared.popped, epoch);
+            shared.send_waiters.fetch_sub(1, Relaxed);
+        }
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Sender<T> {
+        self.shared.senders.fetch_add(1, Relaxed);
+        Sender {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if self.shared.senders.fetch_sub(1, SeqCst) == 1 {
+            Shared::<T>::disconnect(&self.shared.pushed);
+        }
+    }
+}
+
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sender").finish_non_exhaustive()
+    }
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Receiver<T> {
+    pub fn recv(&self) -> Result<T, RecvError> {
+        self.recv_until(None).map_err(|_| RecvError)
+    }
+
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        if let Some(msg) = self.pop() {
+            return Ok(msg);
+        }
+        if self.shared.senders.load(SeqCst) == 0 {
+            // A message might have been sent right before the last sender was dropped
+            return self.pop().ok_or(TryRecvError::Disconnected);
+        }
+        Err(TryRecvError::Empty)
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        self.recv_until(deadline(timeout))
+    }
+
+    pub fn recv_deadline(&self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        self.recv_until(Some(deadline))
+    }
+
+    fn recv_until(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        let shared = &self.shared;
+        loop {
+            let epoch = shared.pushed.load(SeqCst);
+            match self.try_recv() {
+                Ok(msg) => return Ok(msg),
+                Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+                Err(TryRecvError::Empty) => {}
+            }
+            // Empty, sleep until a sender pushes something (or the last one is dropped)
+            shared.recv_waiters.fetch_add(1, SeqCst);
+            let timed_out = match deadline {
+                None => {
+                    futex::wait(&shared.pushed, epoch);
+                    false
+                }
+                Some(deadline) => {
+                    let now = Instant::now();
+                    now >= deadline || !futex::wait_timeout(&shared.pushed, epoch, deadline - now)
+                }
+            };
+            shared.recv_waiters.fetch_sub(1, Relaxed);
+            if timed_out {
+                // One last look, a message could have arrived right at the deadline,
+                // or the last sender could have been dropped while we were parked
+                return self.try_recv().map_err(|e| match e {
+                    TryRecvError::Empty => RecvTimeoutError::Timeout,
+                    TryRecvError::Disconnected => RecvTimeoutError::Disconnected,
+                });
+            }
+        }
+    }
+
+    fn pop(&self) -> Option<T> {
+        let msg = self.shared.try_pop()?;
+        Shared::<T>::notify(&self.shared.popped, &self.shared.send_waiters);
+        Some(msg)
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Receiver<T> {
+        self.shared.receivers.fetch_add(1, Relaxed);
+        Receiver {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        if self.shared.receivers.fetch_sub(1, SeqCst) == 1 {
+            Shared::<T>::disconnect(&self.shared.popped);
+        }
+    }
+}
+
+impl<T> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Receiver").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test() {
+        let (sender, receiver) = bounded(1);
+        let t = thread::spawn(move || {
+            sender.send(1).unwrap();
+            sender.send(2).unwrap();
+        });
+        assert_eq!(receiver.recv(), Ok(1));
+        assert_eq!(receiver.recv(), Ok(2));
+        t.join().unwrap();
+    }
+
+    #[test]
+    fn test_wrap_around() {
+        // Capacity is not a power of two, positions go around the ring many times
+        let (sender, receiver) = bounded(3);
+        for lap in 0..100 {
+            for i in 0..3 {
+                sender.send(lap * 3 + i).unwrap();
+            }
+            assert_eq!(receiver.try_recv(), Ok(lap * 3));
+            sender.send(-1).unwrap();
+            assert_eq!(receiver.recv(), Ok(lap * 3 + 1));
+            assert_eq!(receiver.recv(), Ok(lap * 3 + 2));
+            assert_eq!(receiver.recv(), Ok(-1));
+            assert_eq!(receiver.try_recv(), Err(TryRecvError::Empty));
+        }
+    }
+
+    #[test]
+    fn test_back_pressure() {
+        let (sender, receiver) = bounded(2);
+        let sent = AtomicUsize::new(0);
+
+        thread::scope(|s| {
+            s.spawn(|| {
+                for i in 0..3 {
+                    sender.send(i).unwrap();
+                    sent.fetch_add(1, Relaxed);
+                }
+            });
+
+            thread::sleep(Duration::from_millis(50));
+            // Ring is full, third send must sleep until a receive
+            assert_eq!(sent.load(Relaxed), 2);
+
+            assert_eq!(receiver.recv(), Ok(0));
+        });
+
+        assert_eq!(sent.load(Relaxed), 3);
+        assert_eq!(receiver.recv(), Ok(1));
+        assert_eq!(receiver.recv(), Ok(2));
+    }
+
+    #[test]
+    fn test_multi_producer_multi_consumer() {
+        const PRODUCERS: usize = 4;
+        const CONSUMERS: usize = 4;
+        const MESSAGES: usize = 10_000;
+
+        // Small ring so both the full and the empty case happen a lot
+        let (sender, receiver) = bounded(4);
+        let sum = AtomicUsize::new(0);
+        let count = AtomicUsize::new(0);
+
+        thread::scope(|s| {
+            for p in 0..PRODUCERS {
+                let sender = sender.clone();
+                s.spawn(move || {
+                    for i in 0..MESSAGES {
+                        sender.send(p * MESSAGES + i).unwrap();
+                    }
+                });
+            }
+            drop(sender);
+            for _ in 0..CONSUMERS {
+                let receiver = receiver.clone();
+                let (sum, count) = (&sum, &count);
+                s.spawn(move || {
+                    while let Ok(msg) = receiver.recv() {
+                        sum.fetch_add(msg, Relaxed);
+                        count.fetch_add(1, Relaxed);
+                    }
+                });
+            }
+        });
+
+        let n = PRODUCERS * MESSAGES;
+        assert_eq!(count.load(Relaxed), n);
+        assert_eq!(sum.load(Relaxed), n * (n - 1) / 2);
+    }
+
+    #[test]
+    fn test_disconnect() {
+        let (sender, receiver) = bounded(4);
+        sender.send(1).unwrap();
+        drop(sender);
+        // Queued messages are drained before reporting the disconnection
+        assert_eq!(receiver.recv(), Ok(1));
+        assert_eq!(receiver.recv(), Err(RecvError));
+
+        let (sender, receiver) = bounded(1);
+        sender.send(1).unwrap();
+        thread::scope(|s| {
+            // Ring is full, so this send sleeps until the receiver is gone
+            let t = s.spawn(|| sender.send(2));
+            thread::sleep(Duration::from_millis(50));
+            drop(receiver);
+            assert_eq!(t.join().unwrap(), Err(SendError(2)));
+        });
+
+        let (sender, receiver) = bounded::<i32>(1);
+        thread::scope(|s| {
+            let t = s.spawn(move || receiver.recv());
+            thread::sleep(Duration::from_millis(50));
+            drop(sender);
+            assert_eq!(t.join().unwrap(), Err(RecvError));
+        });
+    }
+
+    #[test]
+    fn test_recv_timeout() {
+        let (sender, receiver) = bounded(1);
+
+        let start = Instant::now();
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_millis(50)),
+            Err(RecvTimeoutError::Timeout)
+        );
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(20));
+                sender.send(1).unwrap();
+            });
+            assert_eq!(receiver.recv_timeout(Duration::from_secs(10)), Ok(1));
+        });
+
+        sender.send(2).unwrap();
+        assert_eq!(receiver.recv_deadline(Instant::now()), Ok(2));
+        drop(sender);
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_secs(10)),
+            Err(RecvTimeoutError::Disconnected)
+        );
+    }
+
+    #[test]
+    fn test_sender_dropped_near_deadline() {
+        // Last sender is dropped while the receiver is parked, from a bit before to a bit after
+        // the deadline: once it's gone before the deadline, it must never be reported as Timeout
+        for offset in 0..20u64 {
+            let (sender, receiver) = bounded::<i32>(1);
+            let deadline = Instant::now() + Duration::from_millis(20);
+            let drop_at = deadline - Duration::from_millis(10) + Duration::from_millis(offset);
+            thread::scope(|s| {
+                let t = s.spawn(move || {
+                    thread::sleep(drop_at.saturating_duration_since(Instant::now()));
+                    drop(sender);
+                    Instant::now()
+                });
+                let result = receiver.recv_deadline(deadline);
+                let dropped_at = t.join().unwrap();
+                if dropped_at < deadline {
+                    assert_eq!(result, Err(RecvTimeoutError::Disconnected));
+                }
+            });
+            assert_eq!(receiver.try_recv(), Err(TryRecvError::Disconnected));
+        }
+    }
+
+    #[test]
+    fn test_drop_unreceived_messages() {
+        let msg = std::sync::Arc::new(());
+        let (sender, receiver) = bounded(4);
+        for _ in 0..3 {
+            sender.send(msg.clone()).unwrap();
+        }
+        receiver.recv().unwrap();
+        drop((sender, receiver));
+        assert_eq!(std::sync::Arc::strong_count(&msg), 1);
+    }
+}
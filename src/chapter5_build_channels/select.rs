@@ -0,0 +1,201 @@
+use crate::chapter5_build_channels::deadline::{deadline, park_until};
+use crate::chapter5_build_channels::mpmc_version::{Receiver, Selectable};
+use std::cell::Cell;
+use std::fmt;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Wait on several receivers at once, like select() on file descriptors
+// The waiting thread is registered with every channel, a send on any of them unparks it
+// Select only tells which receiver is ready, the message is still taken with try_recv
+// If other threads receive from the same channel they can take the message first, so be ready for Empty
+pub struct Select<'a> {
+    receivers: Vec<&'a dyn Selectable>,
+    // Where the next scan starts, so a busy receiver can't starve the ones added after it
+    next: Cell<usize>,
+}
+
+impl<'a> Select<'a> {
+    pub fn new() -> Select<'a> {
+        Select {
+            receivers: Vec::new(),
+            next: Cell::new(0),
+        }
+    }
+
+    // Index of a receiver is the order it was added in, starting from 0
+    pub fn recv<T>(mut self, receiver: &'a Receiver<T>) -> Select<'a> {
+        self.receivers.push(receiver);
+        self
+    }
+
+    // Block until one of the receivers is ready (has a message or is disconnected)
+    pub fn ready(&self) -> usize {
+        assert!(!self.receivers.is_empty(), "no receivers to select on");
+        self.wait_until(None).unwrap()
+    }
+
+    // None if nothing is ready
+    pub fn try_ready(&self) -> Option<usize> {
+        let n = self.receivers.len();
+        let start = self.next.get();
+        let index = (0..n)
+            .map(|i| (start + i) % n)
+            .find(|&i| self.receivers[i].is_ready())?;
+        self.next.set((index + 1) % n);
+        Some(index)
+    }
+
+    // None if nothing got ready in time
+    pub fn ready_timeout(&self, timeout: Duration) -> Option<usize> {
+        match deadline(timeout) {
+            Some(deadline) => self.wait_until(Some(deadline)),
+            None => Some(self.ready()),
+        }
+    }
+
+    pub fn ready_deadline(&self, deadline: Instant) -> Option<usize> {
+        self.wait_until(Some(deadline))
+    }
+
+    fn wait_until(&self, deadline: Option<Instant>) -> Option<usize> {
+        // Register before checking, a send after the check then unparks us
+        // and park returns right away if that happened before we park
+        let current = thread::current();
+        for receiver in &self.receivers {
+            receiver.register(current.clone());
+        }
+        let result = loop {
+            if let Some(index) = self.try_ready() {
+                break Some(index);
+            }
+            if !park_until(deadline) {
+                break None;
+            }
+        };
+        for receiver in &self.receivers {
+            receiver.unregister(current.id());
+        }
+        result
+    }
+}
+
+impl Default for Select<'_> {
+    fn default() -> Self {
+        Select::new()
+    }
+}
+
+impl fmt::Debug for Select<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Select")
+            .field("receivers", &self.receivers.len())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chapter5_build_channels::error::{RecvError, TryRecvError};
+    use crate::chapter5_build_channels::mpmc_version::{bounded, unbounded};
+
+    #[test]
+    fn test() {
+        let (sender1, receiver1) = unbounded::<i32>();
+        let (sender2, receiver2) = unbounded::<&str>();
+        let select = Select::new().recv(&receiver1).recv(&receiver2);
+        assert_eq!(select.try_ready(), None);
+
+        thread::scope(|s| {
+            s.spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                sender2.send("hello").unwrap();
+            });
+            assert_eq!(select.ready(), 1);
+            assert_eq!(receiver2.try_recv(), Ok("hello"));
+        });
+
+        sender1.send(1).unwrap();
+        assert_eq!(select.ready(), 0);
+        assert_eq!(receiver1.try_recv(), Ok(1));
+        // Sender of the second channel is gone, recv wouldn't block anymore
+        assert_eq!(select.ready(), 1);
+        assert_eq!(receiver2.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn test_timeout() {
+        let (_sender1, receiver1) = bounded::<i32>(1);
+        let (sender2, receiver2) = bounded::<i32>(1);
+        let select = Select::new().recv(&receiver1).recv(&receiver2);
+
+        let start = Instant::now();
+        assert_eq!(select.ready_timeout(Duration::from_millis(20)), None);
+        assert!(start.elapsed() >= Duration::from_millis(20));
+
+        sender2.send(2).unwrap();
+        assert_eq!(select.ready_deadline(Instant::now()), Some(1));
+        assert_eq!(select.ready_timeout(Duration::MAX), Some(1));
+    }
+
+    #[test]
+    fn test_fairness() {
+        let (sender1, receiver1) = unbounded();
+        let (sender2, receiver2) = unbounded();
+        for i in 0..3 {
+            sender1.send(i).unwrap();
+            sender2.send(i).unwrap();
+        }
+        let select = Select::new().recv(&receiver1).recv(&receiver2);
+        // Both stay ready, but they take turns
+        let order: Vec<usize> = (0..4).map(|_| select.ready()).collect();
+        assert_eq!(order, [0, 1, 0, 1]);
+    }
+
+    #[test]
+    fn test_event_loop() {
+        const MESSAGES: i32 = 1000;
+
+        let (numbers, receiver1) = bounded(4);
+        let (words, receiver2) = bounded(4);
+        let select = Select::new().recv(&receiver1).recv(&receiver2);
+
+        thread::scope(|s| {
+            s.spawn(move || {
+                for i in 0..MESSAGES {
+                    numbers.send(i).unwrap();
+                }
+            });
+            s.spawn(move || {
+                for _ in 0..MESSAGES {
+                    words.send("word").unwrap();
+                }
+            });
+
+            let (mut sum, mut count) = (0, 0);
+            let mut closed = [false, false];
+            while closed != [true, true] {
+                // A disconnected receiver stays ready, the other one still gets its turn
+                match select.ready() {
+                    0 => match receiver1.recv() {
+                        Ok(n) => sum += n,
+                        Err(RecvError) => closed[0] = true,
+                    },
+                    _ => match receiver2.recv() {
+                        Ok(_) => count += 1,
+                        Err(RecvError) => closed[1] = true,
+                    },
+                }
+            }
+            assert_eq!(sum, MESSAGES * (MESSAGES - 1) / 2);
+            assert_eq!(count, MESSAGES);
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "no receivers to select on")]
+    fn test_empty() {
+        Select::new().ready();
+    }
+}
@@ -1,52 +1,335 @@
+use crate::chapter5_build_channels::deadline::deadline;
+use crate::chapter5_build_channels::error::{RecvError, RecvTimeoutError, SendError, TryRecvError};
+use crate::chapter5_build_channels::stats::{self, Blocked, ChannelStats, Op, Stats};
 use std::collections::VecDeque;
+use std::fmt;
 use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
 
-pub(crate) struct Channel<T> {
-    msg_queue: Mutex<VecDeque<T>>,
+// There are no handles to count, so the channel is disconnected by calling close:
+// a producer closes it when it's done, the consumer when it won't receive anymore
+// Messages queued before close can still be received, anything sent after it is given back
+struct State<T> {
+    queue: VecDeque<T>,
+    closed: bool,
+}
+
+pub struct Channel<T> {
+    // closed is kept under the same lock as the queue, so a receiver can't miss it before waiting
+    state: Mutex<State<T>>,
     ready: Condvar,
+    // Only kept when asked for with with_stats
+    stats: Option<Stats>,
 }
 
 impl<T> Channel<T> {
     pub const fn new() -> Channel<T> {
         Channel {
-            msg_queue: Mutex::new(VecDeque::new()),
+            state: Mutex::new(State {
+                queue: VecDeque::new(),
+                closed: false,
+            }),
+            ready: Condvar::new(),
+            stats: None,
+        }
+    }
+
+    // Same channel, but counting what goes through it, see stats()
+    pub const fn with_stats() -> Channel<T> {
+        Channel {
+            state: Mutex::new(State {
+                queue: VecDeque::new(),
+                closed: false,
+            }),
             ready: Condvar::new(),
+            stats: Some(Stats::new()),
         }
     }
 
-    pub fn send(&self, msg: T) {
-        self.msg_queue.lock().unwrap().push_back(msg);
+    // Fail only once the channel is closed, the message is given back in the error
+    pub fn send(&self, msg: T) -> Result<(), SendError<T>> {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return Err(SendError(msg));
+        }
+        state.queue.push_back(msg);
+        let depth = state.queue.len();
+        drop(state);
         self.ready.notify_one();
+        if let Some(stats) = &self.stats {
+            stats.record_send(depth);
+        }
+        Ok(())
     }
 
-    pub fn receive(&self) -> T {
-        let mut guard = self.msg_queue.lock().unwrap();
+    // RecvError once the channel is closed and every queued message has been received
+    pub fn receive(&self) -> Result<T, RecvError> {
+        let mut guard = self.state.lock().unwrap();
+        let _blocked = self.blocked(waits(&guard));
         loop {
-            if let Some(msg) = guard.pop_front() {
-                return msg;
+            if let Some(msg) = self.pop(&mut guard) {
+                return Ok(msg);
+            }
+            if guard.closed {
+                return Err(RecvError);
             }
             guard = self.ready.wait(guard).unwrap();
         }
     }
+
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        let mut guard = self.state.lock().unwrap();
+        if let Some(msg) = self.pop(&mut guard) {
+            return Ok(msg);
+        }
+        if guard.closed {
+            return Err(TryRecvError::Disconnected);
+        }
+        Err(TryRecvError::Empty)
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        match deadline(timeout) {
+            Some(deadline) => self.recv_deadline(deadline),
+            None => self.receive().map_err(RecvTimeoutError::from),
+        }
+    }
+
+    pub fn recv_deadline(&self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        let mut guard = self.state.lock().unwrap();
+        // A call that times out was blocked too
+        let _blocked = self.blocked(waits(&guard) && Instant::now() < deadline);
+        loop {
+            if let Some(msg) = self.pop(&mut guard) {
+                return Ok(msg);
+            }
+            if guard.closed {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            guard = self.ready.wait_timeout(guard, deadline - now).unwrap().0;
+        }
+    }
+
+    // Disconnect both sides, every waiting receiver wakes up
+    // Closing twice does nothing
+    pub fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.ready.notify_all();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.state.lock().unwrap().closed
+    }
+
+    // Number of messages waiting right now, can be outdated as soon as it's returned
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Always None, send never waits for room
+    pub fn capacity(&self) -> Option<usize> {
+        None
+    }
+
+    // None if the channel was made with new instead of with_stats
+    // Doesn't take the queue lock, so it's fine to call often from a monitoring thread
+    pub fn stats(&self) -> Option<ChannelStats> {
+        self.stats.as_ref().map(Stats::snapshot)
+    }
+
+    fn pop(&self, state: &mut State<T>) -> Option<T> {
+        let msg = state.queue.pop_front()?;
+        if let Some(stats) = &self.stats {
+            stats.record_recv(1);
+        }
+        Some(msg)
+    }
+
+    fn blocked(&self, waits: bool) -> Option<Blocked<'_>> {
+        stats::blocked(self.stats.as_ref(), Op::Recv, waits)
+    }
+}
+
+// receive has to wait: nothing queued, but the channel isn't closed yet
+fn waits<T>(state: &State<T>) -> bool {
+    state.queue.is_empty() && !state.closed
+}
+
+impl<T> Default for Channel<T> {
+    fn default() -> Channel<T> {
+        Channel::new()
+    }
+}
+
+impl<T> fmt::Debug for Channel<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Channel").finish_non_exhaustive()
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::chapter5_build_channels::error::{
+        RecvError, RecvTimeoutError, SendError, TryRecvError,
+    };
     use crate::chapter5_build_channels::simple_version::Channel;
     use std::thread;
+    use std::time::{Duration, Instant};
 
     #[test]
     fn test() {
         let channel = Channel::new();
         thread::scope(|s| {
             s.spawn(|| {
-                channel.send(1);
+                channel.send(1).unwrap();
             });
             s.spawn(|| {
                 let msg = channel.receive();
-                assert_eq!(msg, 1);
+                assert_eq!(msg, Ok(1));
+            });
+        });
+    }
+
+    #[test]
+    fn test_close_after_send() {
+        let channel = Channel::new();
+        channel.send(1).unwrap();
+        channel.send(2).unwrap();
+        channel.close();
+        assert!(channel.is_closed());
+        // Messages sent before close are still delivered, in order
+        assert_eq!(channel.receive(), Ok(1));
+        assert_eq!(channel.try_recv(), Ok(2));
+        assert_eq!(channel.receive(), Err(RecvError));
+        assert_eq!(channel.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn test_close_while_receiving() {
+        let channel = Channel::new();
+        thread::scope(|s| {
+            let receivers: Vec<_> = (0..2).map(|_| s.spawn(|| channel.receive())).collect();
+            // Producer is done after one message, it closes the channel on its way out
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(20));
+                channel.send(1).unwrap();
+                channel.close();
+            });
+            // notify_all, the receiver that didn't get the message isn't left blocked
+            let results: Vec<_> = receivers.into_iter().map(|r| r.join().unwrap()).collect();
+            assert!(results.contains(&Ok(1)));
+            assert!(results.contains(&Err(RecvError)));
+        });
+    }
+
+    #[test]
+    fn test_close_before_send() {
+        let channel = Channel::new();
+        // Receiver side is gone first, nobody would ever receive the message
+        channel.close();
+        assert_eq!(channel.send("hello"), Err(SendError("hello")));
+        assert!(channel.is_empty());
+        // Closing again changes nothing
+        channel.close();
+        assert_eq!(channel.receive(), Err(RecvError));
+    }
+
+    #[test]
+    fn test_close_while_waiting_with_timeout() {
+        let channel = Channel::<i32>::new();
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(20));
+                channel.close();
             });
+            let start = Instant::now();
+            assert_eq!(
+                channel.recv_deadline(start + Duration::from_secs(10)),
+                Err(RecvTimeoutError::Disconnected)
+            );
+            assert!(start.elapsed() < Duration::from_secs(10));
         });
-        println!("Done");
+        assert_eq!(
+            channel.recv_timeout(Duration::MAX),
+            Err(RecvTimeoutError::Disconnected)
+        );
+    }
+
+    #[test]
+    fn test_try_recv_and_timeout() {
+        let channel = Channel::new();
+        assert_eq!(channel.try_recv(), Err(TryRecvError::Empty));
+        assert_eq!(
+            channel.recv_timeout(Duration::from_millis(20)),
+            Err(RecvTimeoutError::Timeout)
+        );
+
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(20));
+                channel.send(1).unwrap();
+            });
+            assert_eq!(channel.recv_timeout(Duration::from_secs(10)), Ok(1));
+        });
+
+        channel.send(2).unwrap();
+        assert_eq!(channel.recv_deadline(Instant::now()), Ok(2));
+        channel.send(3).unwrap();
+        assert_eq!(channel.try_recv(), Ok(3));
+    }
+
+    #[test]
+    fn test_len() {
+        let channel = Channel::new();
+        assert!(channel.is_empty());
+        assert_eq!(channel.capacity(), None);
+        channel.send(1).unwrap();
+        channel.send(2).unwrap();
+        assert_eq!(channel.len(), 2);
+        channel.receive().unwrap();
+        assert_eq!(channel.len(), 1);
+        assert_eq!(channel.stats(), None);
+    }
+
+    #[test]
+    fn test_stats() {
+        let channel = Channel::with_stats();
+        for i in 0..3 {
+            channel.send(i).unwrap();
+        }
+        assert_eq!(channel.receive(), Ok(0));
+        assert_eq!(channel.try_recv(), Ok(1));
+        // Nothing waited yet
+        let stats = channel.stats().unwrap();
+        assert_eq!((stats.sent, stats.received, stats.max_depth), (3, 2, 3));
+        assert_eq!((stats.blocked_recvs, stats.wait_time), (0, Duration::ZERO));
+
+        assert_eq!(channel.receive(), Ok(2));
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(20));
+                channel.send(3).unwrap();
+            });
+            assert_eq!(channel.receive(), Ok(3));
+        });
+        assert_eq!(
+            channel.recv_timeout(Duration::from_millis(20)),
+            Err(RecvTimeoutError::Timeout)
+        );
+
+        let stats = channel.stats().unwrap();
+        assert_eq!((stats.sent, stats.received, stats.max_depth), (4, 4, 3));
+        // The receive that waited for the other thread and the one that timed out
+        assert_eq!(stats.blocked_recvs, 2);
+        assert_eq!(stats.blocked_sends, 0);
+        assert!(stats.wait_time >= Duration::from_millis(20));
     }
 }
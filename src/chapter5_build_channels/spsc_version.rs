@@ -0,0 +1,288 @@
+use crate::chapter6_build_arc::weak_pointer::Arc;
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::mem::MaybeUninit;
+use std::ops::Deref;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::{Acquire, Release};
+
+// Single producer single consumer ring buffer
+// Only the producer writes tail and only the consumer writes head, so there is no CAS at all:
+// every push and pop finishes in a bounded number of steps (wait-free), nobody ever blocks
+// Handles are not Clone, so there can't be a second producer or consumer
+
+// Keep head and tail on their own cache lines, otherwise every push invalidates the
+// consumer's cache line and every pop the producer's one (false sharing)
+#[repr(align(64))]
+struct CachePadded<T>(T);
+
+impl<T> Deref for CachePadded<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+struct Shared<T> {
+    buffer: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    // Next position to pop, written by the consumer only
+    head: CachePadded<AtomicUsize>,
+    // Next position to push, written by the producer only
+    // Positions are taken modulo the capacity, they won't wrap around usize in practice
+    tail: CachePadded<AtomicUsize>,
+}
+
+unsafe impl<T: Send> Sync for Shared<T> {}
+
+impl<T> Shared<T> {
+    fn slot(&self, pos: usize) -> *mut MaybeUninit<T> {
+        self.buffer[pos % self.buffer.len()].get()
+    }
+}
+
+impl<T> Drop for Shared<T> {
+    fn drop(&mut self) {
+        let tail = *self.tail.0.get_mut();
+        let mut pos = *self.head.0.get_mut();
+        while pos != tail {
+            unsafe { (*self.slot(pos)).assume_init_drop() };
+            pos = pos.wrapping_add(1);
+        }
+    }
+}
+
+pub fn bounded<T>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    assert!(capacity > 0, "capacity must be greater than zero");
+    let shared = Arc::new(Shared {
+        buffer: (0..capacity)
+            .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+            .collect(),
+        head: CachePadded(AtomicUsize::new(0)),
+        tail: CachePadded(AtomicUsize::new(0)),
+    });
+    (
+        Producer {
+            shared: shared.clone(),
+            tail: 0,
+            cached_head: 0,
+        },
+        Consumer {
+            shared,
+            head: 0,
+            cached_tail: 0,
+        },
+    )
+}
+
+pub struct Producer<T> {
+    shared: Arc<Shared<T>>,
+    // Own copy of shared.tail, nobody else changes it
+    tail: usize,
+    // Last head we saw, only reload the real one (other core's cache line) when this looks full
+    cached_head: usize,
+}
+
+impl<T> Producer<T> {
+    // Number of free slots, at least `wanted` if possible
+    fn free(&mut self, wanted: usize) -> usize {
+        let capacity = self.shared.buffer.len();
+        let mut free = capacity - self.tail.wrapping_sub(self.cached_head);
+        if free < wanted {
+            // Acquire pairs with the consumer's Release, it's done reading those slots
+            self.cached_head = self.shared.head.load(Acquire);
+            free = capacity - self.tail.wrapping_sub(self.cached_head);
+        }
+        free
+    }
+
+    // Give the message back when full
+    pub fn push(&mut self, msg: T) -> Result<(), T> {
+        if self.free(1) == 0 {
+            return Err(msg);
+        }
+        unsafe { (*self.shared.slot(self.tail)).write(msg) };
+        self.tail = self.tail.wrapping_add(1);
+        self.shared.tail.store(self.tail, Release);
+        Ok(())
+    }
+
+    // Push as many as fit, return how many were pushed
+    // The tail is published once for the whole batch
+    pub fn push_slice(&mut self, msgs: &[T]) -> usize
+    where
+        T: Clone,
+    {
+        let n = self.free(msgs.len()).min(msgs.len());
+        for (i, msg) in msgs[..n].iter().enumerate() {
+            // If clone panics the messages written so far are never published, only leaked
+            unsafe { (*self.shared.slot(self.tail.wrapping_add(i))).write(msg.clone()) };
+        }
+        self.tail = self.tail.wrapping_add(n);
+        self.shared.tail.store(self.tail, Release);
+        n
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.shared.buffer.len()
+    }
+}
+
+impl<T> fmt::Debug for Producer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Producer").finish_non_exhaustive()
+    }
+}
+
+pub struct Consumer<T> {
+    shared: Arc<Shared<T>>,
+    head: usize,
+    cached_tail: usize,
+}
+
+impl<T> Consumer<T> {
+    // Number of messages ready to pop, at least `wanted` if possible
+    fn available(&mut self, wanted: usize) -> usize {
+        let mut available = self.cached_tail.wrapping_sub(self.head);
+        if available < wanted {
+            // Acquire pairs with the producer's Release, the messages are fully written
+            self.cached_tail = self.shared.tail.load(Acquire);
+            available = self.cached_tail.wrapping_sub(self.head);
+        }
+        available
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        if self.available(1) == 0 {
+            return None;
+        }
+        let msg = unsafe { (*self.shared.slot(self.head)).assume_init_read() };
+        self.head = self.head.wrapping_add(1);
+        self.shared.head.store(self.head, Release);
+        Some(msg)
+    }
+
+    // Fill buf from the front with as many messages as are ready, return how many
+    // The head is published once for the whole batch
+    pub fn pop_into(&mut self, buf: &mut [T]) -> usize {
+        let n = self.available(buf.len()).min(buf.len());
+        for (i, out) in buf[..n].iter_mut().enumerate() {
+            *out = unsafe { (*self.shared.slot(self.head.wrapping_add(i))).assume_init_read() };
+        }
+        self.head = self.head.wrapping_add(n);
+        self.shared.head.store(self.head, Release);
+        n
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.shared.buffer.len()
+    }
+}
+
+impl<T> fmt::Debug for Consumer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Consumer").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test() {
+        let (mut producer, mut consumer) = bounded(2);
+        assert_eq!(consumer.pop(), None);
+        assert_eq!(producer.push(1), Ok(()));
+        assert_eq!(producer.push(2), Ok(()));
+        assert_eq!(producer.push(3), Err(3));
+        assert_eq!(consumer.pop(), Some(1));
+        assert_eq!(producer.push(3), Ok(()));
+        assert_eq!(consumer.pop(), Some(2));
+        assert_eq!(consumer.pop(), Some(3));
+        assert_eq!(consumer.pop(), None);
+    }
+
+    #[test]
+    fn test_batch() {
+        let (mut producer, mut consumer) = bounded(5);
+        assert_eq!(producer.push_slice(&[1, 2, 3]), 3);
+        // Only 2 slots left
+        assert_eq!(producer.push_slice(&[4, 5, 6, 7]), 2);
+
+        let mut buf = [0; 4];
+        assert_eq!(consumer.pop_into(&mut buf), 4);
+        assert_eq!(buf, [1, 2, 3, 4]);
+
+        // Wraps around the end of the buffer
+        assert_eq!(producer.push_slice(&[6, 7, 8, 9, 10]), 4);
+        assert_eq!(consumer.pop_into(&mut buf), 4);
+        assert_eq!(buf, [5, 6, 7, 8]);
+        assert_eq!(consumer.pop_into(&mut buf), 1);
+        assert_eq!(buf[0], 9);
+        assert_eq!(consumer.pop_into(&mut buf), 0);
+    }
+
+    #[test]
+    fn test_between_threads() {
+        const MESSAGES: u64 = 100_000;
+
+        let (mut producer, mut consumer) = bounded(16);
+        thread::scope(|s| {
+            s.spawn(move || {
+                let mut next = 0;
+                while next < MESSAGES {
+                    let pushed = if next % 2 == 0 {
+                        let batch: Vec<u64> = (next..MESSAGES.min(next + 5)).collect();
+                        producer.push_slice(&batch) as u64
+                    } else {
+                        producer.push(next).map_or(0, |_| 1)
+                    };
+                    if pushed == 0 {
+                        // Full, let the consumer run
+                        thread::yield_now();
+                    }
+                    next += pushed;
+                }
+            });
+
+            // Messages arrive in order, none is lost or duplicated
+            let mut expected = 0;
+            let mut buf = [0; 3];
+            while expected < MESSAGES {
+                let n = consumer.pop_into(&mut buf);
+                for &msg in &buf[..n] {
+                    assert_eq!(msg, expected);
+                    expected += 1;
+                }
+                if let Some(msg) = consumer.pop() {
+                    assert_eq!(msg, expected);
+                    expected += 1;
+                } else if n == 0 {
+                    thread::yield_now();
+                }
+            }
+            assert_eq!(consumer.pop(), None);
+        });
+    }
+
+    #[test]
+    fn test_drop_unreceived_messages() {
+        let msg = std::sync::Arc::new(());
+        let (mut producer, mut consumer) = bounded(4);
+        assert_eq!(
+            producer.push_slice(&[msg.clone(), msg.clone(), msg.clone()]),
+            3
+        );
+        consumer.pop().unwrap();
+        drop((producer, consumer));
+        assert_eq!(std::sync::Arc::strong_count(&msg), 1);
+    }
+
+    #[test]
+    fn test_send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<Producer<String>>();
+        assert_send::<Consumer<String>>();
+    }
+}
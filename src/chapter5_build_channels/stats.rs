@@ -0,0 +1,101 @@
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::atomic::{AtomicU64, AtomicUsize};
+use std::time::{Duration, Instant};
+
+// Counters a channel can keep next to its queue, to see how it's used (depth, contention)
+// They are plain atomics updated after the fact, so reading them never takes the queue lock
+// Each counter is read on its own, a snapshot taken while the channel is busy can be a bit
+// inconsistent (received ahead of sent for a moment), good enough for monitoring
+// Only blocking calls count as blocked, a task in recv_async/send_async doesn't hold a thread
+pub(crate) struct Stats {
+    sent: AtomicU64,
+    received: AtomicU64,
+    blocked_sends: AtomicU64,
+    blocked_recvs: AtomicU64,
+    max_depth: AtomicUsize,
+    // Sum of the time spent blocked, in send and receive together
+    wait_nanos: AtomicU64,
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct ChannelStats {
+    pub sent: u64,
+    pub received: u64,
+    pub blocked_sends: u64,
+    pub blocked_recvs: u64,
+    pub max_depth: usize,
+    pub wait_time: Duration,
+}
+
+pub(crate) enum Op {
+    Send,
+    Recv,
+}
+
+impl Stats {
+    pub(crate) const fn new() -> Stats {
+        Stats {
+            sent: AtomicU64::new(0),
+            received: AtomicU64::new(0),
+            blocked_sends: AtomicU64::new(0),
+            blocked_recvs: AtomicU64::new(0),
+            max_depth: AtomicUsize::new(0),
+            wait_nanos: AtomicU64::new(0),
+        }
+    }
+
+    // depth is the queue length right after the push
+    pub(crate) fn record_send(&self, depth: usize) {
+        self.sent.fetch_add(1, Relaxed);
+        self.max_depth.fetch_max(depth, Relaxed);
+    }
+
+    pub(crate) fn record_recv(&self, n: usize) {
+        self.received.fetch_add(n as u64, Relaxed);
+    }
+
+    // Counts one blocked call right away, its waiting time is added when the guard is dropped
+    pub(crate) fn blocked(&self, op: Op) -> Blocked<'_> {
+        match op {
+            Op::Send => self.blocked_sends.fetch_add(1, Relaxed),
+            Op::Recv => self.blocked_recvs.fetch_add(1, Relaxed),
+        };
+        Blocked {
+            stats: self,
+            start: Instant::now(),
+        }
+    }
+
+    pub(crate) fn snapshot(&self) -> ChannelStats {
+        ChannelStats {
+            sent: self.sent.load(Relaxed),
+            received: self.received.load(Relaxed),
+            blocked_sends: self.blocked_sends.load(Relaxed),
+            blocked_recvs: self.blocked_recvs.load(Relaxed),
+            max_depth: self.max_depth.load(Relaxed),
+            wait_time: Duration::from_nanos(self.wait_nanos.load(Relaxed)),
+        }
+    }
+}
+
+pub(crate) struct Blocked<'a> {
+    stats: &'a Stats,
+    start: Instant,
+}
+
+impl Drop for Blocked<'_> {
+    fn drop(&mut self) {
+        let nanos = self
+            .start
+            .elapsed()
+            .as_nanos()
+            .try_into()
+            .unwrap_or(u64::MAX);
+        self.stats.wait_nanos.fetch_add(nanos, Relaxed);
+    }
+}
+
+// Only when the call is really going to wait and the channel keeps stats at all
+pub(crate) fn blocked(stats: Option<&Stats>, op: Op, waits: bool) -> Option<Blocked<'_>> {
+    stats.filter(|_| waits).map(|stats| stats.blocked(op))
+}
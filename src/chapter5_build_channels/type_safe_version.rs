@@ -1,34 +1,62 @@
-use std::cell::{RefCell, UnsafeCell};
+use crate::chapter5_build_channels::deadline::{deadline, park_until};
+use crate::chapter5_build_channels::error::{RecvError, RecvTimeoutError, SendError, TryRecvError};
+use std::cell::UnsafeCell;
+use std::fmt;
 use std::marker::PhantomData;
 use std::mem::MaybeUninit;
-use std::sync::atomic::AtomicBool;
-use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+use std::ptr;
+use std::sync::atomic::Ordering::{Acquire, Relaxed, Release, SeqCst};
+use std::sync::atomic::{AtomicBool, AtomicPtr};
 use std::thread;
 use std::thread::Thread;
+use std::time::{Duration, Instant};
 
-struct Channel<T> {
+pub struct Channel<T> {
     msg: UnsafeCell<MaybeUninit<T>>,
     ready: AtomicBool,
-    thread: RefCell<Option<Thread>>,
+    // Boxed receiver thread, set once by as_receiver
+    // The sender swaps it out to unpark it, so only one side ever owns the box
+    thread: AtomicPtr<Thread>,
+    // Only one Sender and one Receiver can ever be handed out
+    sender_taken: AtomicBool,
+    receiver_taken: AtomicBool,
+    // Set when a handle is dropped, so the other side knows nobody is there anymore
+    sender_dropped: AtomicBool,
+    receiver_dropped: AtomicBool,
 }
 
-unsafe impl<T> Sync for Channel<T> {}
+unsafe impl<T: Send> Sync for Channel<T> {}
 
 impl<T> Channel<T> {
     pub const fn new() -> Channel<T> {
         Channel {
             msg: UnsafeCell::new(MaybeUninit::uninit()),
             ready: AtomicBool::new(false),
-            thread: RefCell::new(None),
+            thread: AtomicPtr::new(ptr::null_mut()),
+            sender_taken: AtomicBool::new(false),
+            receiver_taken: AtomicBool::new(false),
+            sender_dropped: AtomicBool::new(false),
+            receiver_dropped: AtomicBool::new(false),
         }
     }
 
-    pub fn as_sender(&self) -> Sender<T> {
+    // Panics if called twice, two senders could write the message at the same time
+    pub fn as_sender(&self) -> Sender<'_, T> {
+        if self.sender_taken.swap(true, Relaxed) {
+            panic!("sender has already been taken");
+        }
         Sender { inner: self }
     }
 
-    pub fn as_receiver(&self) -> Receiver<T> {
-        *self.thread.borrow_mut() = Some(thread::current());
+    // Panics if called twice, the thread to unpark must be the one of the only receiver
+    pub fn as_receiver(&self) -> Receiver<'_, T> {
+        if self.receiver_taken.swap(true, Relaxed) {
+            panic!("receiver has already been taken");
+        }
+        // SeqCst with the loads of ready in the receiver and the swap in wake():
+        // either the sender sees our thread, or we see what the sender stored before waking
+        let t = Box::into_raw(Box::new(thread::current()));
+        self.thread.store(t, SeqCst);
         Receiver {
             inner: self,
             _no_send: PhantomData,
@@ -36,6 +64,29 @@ impl<T> Channel<T> {
     }
 }
 
+impl<T> Default for Channel<T> {
+    fn default() -> Channel<T> {
+        Channel::new()
+    }
+}
+
+impl<T> fmt::Debug for Channel<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Channel")
+            .field("ready", &self.ready.load(Relaxed))
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T> Channel<T> {
+    fn wake(&self) {
+        let t = self.thread.swap(ptr::null_mut(), SeqCst);
+        if !t.is_null() {
+            unsafe { Box::from_raw(t) }.unpark();
+        }
+    }
+}
+
 impl<T> Drop for Channel<T> {
     fn drop(&mut self) {
         if self.ready.load(Relaxed) {
@@ -43,65 +94,433 @@ impl<T> Drop for Channel<T> {
                 (*self.msg.get()).assume_init_drop();
             }
         }
+        let t = *self.thread.get_mut();
+        if !t.is_null() {
+            drop(unsafe { Box::from_raw(t) });
+        }
     }
 }
 
-struct Sender<'a, T> {
+pub struct Sender<'a, T> {
     inner: &'a Channel<T>,
 }
 
-unsafe impl<T> Sync for Sender<'_, T> {}
+unsafe impl<T: Send> Sync for Sender<'_, T> {}
 
 impl<T> Sender<'_, T> {
-    pub fn send(&self, msg: T) {
+    // Takes self, so a second send doesn't compile
+    // Nobody would ever read the message, so give it back instead
+    pub fn send(self, msg: T) -> Result<(), SendError<T>> {
+        if self.inner.receiver_dropped.load(Acquire) {
+            return Err(SendError(msg));
+        }
         unsafe {
             (*self.inner.msg.get()).write(msg);
         }
-        if let Some(t) = self.inner.thread.borrow_mut().take() {
-            t.unpark();
-        }
-        self.inner.ready.store(true, Release);
+        // Publish first, then wake up, otherwise the receiver could wake up, see nothing and park again
+        self.inner.ready.store(true, SeqCst);
+        self.inner.wake();
+        Ok(())
+    }
+}
+
+impl<T> fmt::Debug for Sender<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sender").finish_non_exhaustive()
     }
 }
 
-struct Receiver<'a, T> {
+impl<T> Drop for Sender<'_, T> {
+    fn drop(&mut self) {
+        self.inner.sender_dropped.store(true, SeqCst);
+        // Receiver might be parked in recv or recv_timeout, let it see the disconnection
+        self.inner.wake();
+    }
+}
+
+pub struct Receiver<'a, T> {
     inner: &'a Channel<T>,
     // Prevent to be Send to other threads
     // Negative trait (!Send) is not fully implemented, Rust compiler suggests to use marker aka PhantomData
     _no_send: PhantomData<*const ()>,
 }
 
+// Every way to receive takes self, so receiving twice doesn't compile
+// The ones that can fail before the message is there (no message yet, timed out) give the
+// receiver back in the error, so the caller can try again
 impl<T> Receiver<'_, T> {
-    pub fn receive(&self) -> Option<T> {
-        if !self.inner.ready.swap(false, Acquire) {
-            return None;
+    pub fn receive(self) -> Result<T, Self> {
+        match self.take() {
+            Some(msg) => Ok(msg),
+            None => Err(self),
         }
-        unsafe { Some((*self.inner.msg.get()).assume_init_read()) }
+    }
+
+    // Block until the message is there
+    // RecvError if the sender is dropped without sending anything
+    pub fn recv(self) -> Result<T, RecvError> {
+        self.wait_until(None).map_err(|_| RecvError)
     }
 
     pub fn is_ready(&self) -> bool {
         self.inner.ready.load(Relaxed)
     }
+
+    pub fn try_recv(self) -> Result<T, (Self, TryRecvError)> {
+        if let Some(msg) = self.take() {
+            return Ok(msg);
+        }
+        if self.is_disconnected() {
+            return Err((self, TryRecvError::Disconnected));
+        }
+        Err((self, TryRecvError::Empty))
+    }
+
+    pub fn recv_timeout(self, timeout: Duration) -> Result<T, (Self, RecvTimeoutError)> {
+        match self.wait_until(deadline(timeout)) {
+            Ok(msg) => Ok(msg),
+            Err(e) => Err((self, e)),
+        }
+    }
+
+    pub fn recv_deadline(self, deadline: Instant) -> Result<T, (Self, RecvTimeoutError)> {
+        match self.wait_until(Some(deadline)) {
+            Ok(msg) => Ok(msg),
+            Err(e) => Err((self, e)),
+        }
+    }
+
+    // Sender is gone and there is no message left to receive
+    pub fn is_disconnected(&self) -> bool {
+        // Check sender_dropped first, if it's set then a message sent before the drop is visible in ready
+        self.inner.sender_dropped.load(SeqCst) && !self.inner.ready.load(SeqCst)
+    }
+
+    fn take(&self) -> Option<T> {
+        if !self.inner.ready.swap(false, SeqCst) {
+            return None;
+        }
+        unsafe { Some((*self.inner.msg.get()).assume_init_read()) }
+    }
+
+    // Receiver is the thread that called as_receiver (it's not Send), so the sender unparks us
+    fn wait_until(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        loop {
+            if let Some(msg) = self.take() {
+                return Ok(msg);
+            }
+            if self.is_disconnected() {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+            if !park_until(deadline) {
+                return Err(RecvTimeoutError::Timeout);
+            }
+        }
+    }
+}
+
+impl<T> fmt::Debug for Receiver<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Receiver").finish_non_exhaustive()
+    }
+}
+
+impl<T> Drop for Receiver<'_, T> {
+    fn drop(&mut self) {
+        self.inner.receiver_dropped.store(true, Release);
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::chapter5_build_channels::error::{
+        RecvError, RecvTimeoutError, SendError, TryRecvError,
+    };
     use crate::chapter5_build_channels::type_safe_version::Channel;
+    use std::sync::Arc;
     use std::thread;
+    use std::time::{Duration, Instant};
 
     #[test]
     fn test() {
         let channel = Channel::new();
         thread::scope(|s| {
             s.spawn(|| {
-                channel.as_sender().send(1);
+                channel.as_sender().send(1).unwrap();
             });
             s.spawn(|| {
-                let receiver = channel.as_receiver();
-                let msg = receiver.receive();
-                assert_eq!(msg, Some(1));
+                let mut receiver = channel.as_receiver();
+                // Poll until it's there, the receiver comes back every time it's not
+                let msg = loop {
+                    match receiver.receive() {
+                        Ok(msg) => break msg,
+                        Err(r) => receiver = r,
+                    }
+                };
+                assert_eq!(msg, 1);
+            });
+        });
+    }
+
+    #[test]
+    fn test_receiver_dropped() {
+        let channel = Channel::new();
+        let sender = channel.as_sender();
+        drop(channel.as_receiver());
+        assert_eq!(sender.send("hello"), Err(SendError("hello")));
+    }
+
+    #[test]
+    fn test_sender_dropped() {
+        let channel = Channel::<i32>::new();
+        let receiver = channel.as_receiver();
+        let sender = channel.as_sender();
+        assert!(!receiver.is_disconnected());
+        drop(sender);
+        assert!(receiver.is_disconnected());
+        assert!(receiver.receive().is_err());
+    }
+
+    #[test]
+    fn test_sender_dropped_after_send() {
+        let channel = Channel::new();
+        let receiver = channel.as_receiver();
+        channel.as_sender().send(1).unwrap();
+        // Message sent before the sender is gone must still be delivered
+        assert!(!receiver.is_disconnected());
+        assert!(receiver.is_ready());
+        assert_eq!(receiver.receive().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_try_recv() {
+        let channel = Channel::new();
+        let receiver = channel.as_receiver();
+        let sender = channel.as_sender();
+        let (receiver, e) = receiver.try_recv().unwrap_err();
+        assert_eq!(e, TryRecvError::Empty);
+        sender.send(1).unwrap();
+        // Ok consumes the receiver, there is no second try_recv to get Disconnected from
+        assert_eq!(receiver.try_recv().unwrap(), 1);
+
+        let channel = Channel::<i32>::new();
+        let receiver = channel.as_receiver();
+        drop(channel.as_sender());
+        let (_, e) = receiver.try_recv().unwrap_err();
+        assert_eq!(e, TryRecvError::Disconnected);
+    }
+
+    #[test]
+    fn test_recv_timeout() {
+        let channel = Channel::new();
+        let receiver = channel.as_receiver();
+        let start = Instant::now();
+        let (receiver, e) = receiver
+            .recv_timeout(Duration::from_millis(20))
+            .unwrap_err();
+        assert_eq!(e, RecvTimeoutError::Timeout);
+        assert!(start.elapsed() >= Duration::from_millis(20));
+
+        // Timed out receiver is given back and can wait again
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(20));
+                channel.as_sender().send(1).unwrap();
             });
+            assert_eq!(receiver.recv_timeout(Duration::from_secs(10)).unwrap(), 1);
         });
-        println!("Done");
+    }
+
+    #[test]
+    fn test_recv_timeout_disconnected() {
+        let channel = Channel::<i32>::new();
+        let receiver = channel.as_receiver();
+        thread::scope(|s| {
+            let sender = channel.as_sender();
+            s.spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                drop(sender);
+            });
+            let start = Instant::now();
+            let (_, e) = receiver
+                .recv_deadline(start + Duration::from_secs(10))
+                .unwrap_err();
+            assert_eq!(e, RecvTimeoutError::Disconnected);
+            assert!(start.elapsed() < Duration::from_secs(10));
+        });
+    }
+
+    #[test]
+    fn test_recv() {
+        let channel = Channel::new();
+        let receiver = channel.as_receiver();
+        thread::scope(|s| {
+            let sender = channel.as_sender();
+            s.spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                sender.send("hello").unwrap();
+            });
+            // No polling loop, recv parks until the message is there
+            assert_eq!(receiver.recv(), Ok("hello"));
+        });
+    }
+
+    #[test]
+    fn test_recv_ready() {
+        let channel = Channel::new();
+        let receiver = channel.as_receiver();
+        channel.as_sender().send(1).unwrap();
+        assert_eq!(receiver.recv(), Ok(1));
+    }
+
+    #[test]
+    fn test_recv_sender_dropped() {
+        let channel = Channel::<i32>::new();
+        let receiver = channel.as_receiver();
+        thread::scope(|s| {
+            let sender = channel.as_sender();
+            s.spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                drop(sender);
+            });
+            assert_eq!(receiver.recv(), Err(RecvError));
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "receiver has already been taken")]
+    fn test_single_receiver() {
+        let channel = Channel::<i32>::new();
+        let _receiver = channel.as_receiver();
+        channel.as_receiver();
+    }
+
+    #[test]
+    #[should_panic(expected = "sender has already been taken")]
+    fn test_single_sender() {
+        let channel = Channel::<i32>::new();
+        let _sender = channel.as_sender();
+        channel.as_sender();
+    }
+
+    // The tests below race the two sides without any sleep, so every interleaving is possible
+    // They are small enough to run under Miri, which checks the handoff for data races, leaks
+    // and double drops: cargo +nightly miri test --lib type_safe_version
+    const ROUNDS: usize = if cfg!(miri) { 20 } else { 2000 };
+
+    // Races the send (or the drop of the sender) against the receiver going to sleep
+    #[test]
+    fn test_handoff_race() {
+        for i in 0..ROUNDS {
+            let channel = Channel::new();
+            let receiver = channel.as_receiver();
+            thread::scope(|s| {
+                let sender = channel.as_sender();
+                s.spawn(move || {
+                    if i % 2 == 0 {
+                        sender.send(i).unwrap();
+                    }
+                });
+                if i % 2 == 0 {
+                    assert_eq!(receiver.recv(), Ok(i));
+                } else {
+                    let (_, e) = receiver.recv_timeout(Duration::from_secs(10)).unwrap_err();
+                    assert_eq!(e, RecvTimeoutError::Disconnected);
+                }
+            });
+        }
+    }
+
+    // Sender is dropped on its own thread while the receiver parks, the receiver must be
+    // woken up through the thread it stored, and that box must be freed exactly once
+    #[test]
+    fn test_sender_dropped_while_parked() {
+        for _ in 0..ROUNDS {
+            let channel = Channel::<i32>::new();
+            let receiver = channel.as_receiver();
+            thread::scope(|s| {
+                let sender = channel.as_sender();
+                s.spawn(move || drop(sender));
+                assert_eq!(receiver.recv(), Err(RecvError));
+            });
+        }
+    }
+
+    // Receiver goes away on its own thread while the sender sends: either the send sees it
+    // and gives the message back, or the message stays in the channel and is dropped with it
+    #[test]
+    fn test_receiver_dropped_while_sending() {
+        let msg = Arc::new(());
+        for _ in 0..ROUNDS {
+            let channel = Channel::new();
+            thread::scope(|s| {
+                s.spawn(|| drop(channel.as_receiver()));
+                let sender = channel.as_sender();
+                if let Err(SendError(m)) = sender.send(msg.clone()) {
+                    drop(m);
+                }
+            });
+            drop(channel);
+            assert_eq!(Arc::strong_count(&msg), 1);
+        }
+    }
+
+    #[test]
+    fn test_receiver_dropped_before_send() {
+        let msg = Arc::new(());
+        let channel = Channel::new();
+        thread::scope(|s| {
+            s.spawn(|| drop(channel.as_receiver())).join().unwrap();
+            let e = channel.as_sender().send(msg.clone()).unwrap_err();
+            // Given back, not stored in the channel
+            assert_eq!(Arc::strong_count(&msg), 2);
+            drop(e);
+        });
+        drop(channel);
+        assert_eq!(Arc::strong_count(&msg), 1);
+    }
+
+    // Message sent but never received: dropped once with the channel, never leaked
+    #[test]
+    fn test_drop_unreceived_message() {
+        let msg = Arc::new(());
+
+        let channel = Channel::new();
+        channel.as_sender().send(msg.clone()).unwrap();
+        drop(channel);
+        assert_eq!(Arc::strong_count(&msg), 1);
+
+        // Same with a waiting receiver, sent from another thread but never received
+        for _ in 0..ROUNDS {
+            let channel = Channel::new();
+            let receiver = channel.as_receiver();
+            thread::scope(|s| {
+                let sender = channel.as_sender();
+                s.spawn(|| sender.send(msg.clone()).unwrap());
+            });
+            assert!(receiver.is_ready());
+            drop(receiver);
+            drop(channel);
+            assert_eq!(Arc::strong_count(&msg), 1);
+        }
+    }
+
+    // Received message is moved out, the channel must not drop it a second time
+    #[test]
+    fn test_received_message_dropped_once() {
+        let msg = Arc::new(());
+        for _ in 0..ROUNDS {
+            let channel = Channel::new();
+            let receiver = channel.as_receiver();
+            let received = thread::scope(|s| {
+                let sender = channel.as_sender();
+                s.spawn(|| sender.send(msg.clone()).unwrap());
+                receiver.recv().unwrap()
+            });
+            drop(channel);
+            assert_eq!(Arc::strong_count(&msg), 2);
+            drop(received);
+            assert_eq!(Arc::strong_count(&msg), 1);
+        }
     }
 }
@@ -8,7 +8,7 @@ struct Channel<T> {
     ready: AtomicBool
 }
 
-unsafe impl<T> Sync for Channel<T> {}
+unsafe impl<T: Send> Sync for Channel<T> {}
 
 impl<T> Channel<T> {
     pub const fn new() -> Channel<T> {
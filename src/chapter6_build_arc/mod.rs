@@ -1,2 +1,2 @@
 mod basic_reference_counting;
-mod weak_pointer;
+pub(crate) mod weak_pointer;
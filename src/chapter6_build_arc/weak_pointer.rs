@@ -1,4 +1,5 @@
 use std::cell::UnsafeCell;
+use std::fmt;
 use std::mem::ManuallyDrop;
 use std::ops::Deref;
 use std::ptr::NonNull;
@@ -21,7 +22,7 @@ impl<T> ArcInner<T> {
     }
 }
 
-struct Arc<T> {
+pub struct Arc<T> {
     ptr: NonNull<ArcInner<T>>,
 }
 
@@ -62,6 +63,52 @@ impl<T> Arc<T> {
             return Weak { ptr: this.ptr };
         }
     }
+
+    // Like the other counts below, it can change right after it's read, only a hint
+    pub fn strong_count(this: &Self) -> usize {
+        unsafe { this.ptr.as_ref().strong.load(Relaxed) }
+    }
+
+    // Weak pointers only, the weak count also holds one for all the Arcs together
+    pub fn weak_count(this: &Self) -> usize {
+        match unsafe { this.ptr.as_ref().weak.load(Relaxed) } {
+            // Locked by get_mut, which only happens when there were no weak pointers
+            usize::MAX => 0,
+            weak_count => weak_count - 1,
+        }
+    }
+
+    // Both point to the same allocation, not just equal values
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        this.ptr == other.ptr
+    }
+
+    // Only when this is the only Arc and there are no weak pointers, a Weak could upgrade otherwise
+    pub fn get_mut(this: &mut Self) -> Option<&mut T> {
+        let inner = unsafe { this.ptr.as_ref() };
+        // Lock out downgrade by setting the weak count to usize::MAX while checking strong,
+        // otherwise another Arc could downgrade and drop itself between the two checks
+        // and we would miss the new Weak
+        // Acquire pairs with the Release in Weak::drop, so an upgrade done by a Weak that is gone
+        // now is visible in the strong load below
+        if inner
+            .weak
+            .compare_exchange(1, usize::MAX, Acquire, Relaxed)
+            .is_err()
+        {
+            return None;
+        }
+        let is_unique = inner.strong.load(Relaxed) == 1;
+        // Release pairs with the Acquire in downgrade, a Weak made after this sees the unlock
+        // and can't change the strong count we just checked
+        inner.weak.store(1, Release);
+        if !is_unique {
+            return None;
+        }
+        // Pairs with the Release in Arc::drop, other Arcs are done using the value
+        fence(Acquire);
+        Some(unsafe { &mut **inner.value.get() })
+    }
 }
 
 impl<T> Deref for Arc<T> {
@@ -71,6 +118,18 @@ impl<T> Deref for Arc<T> {
     }
 }
 
+impl<T: Default> Default for Arc<T> {
+    fn default() -> Arc<T> {
+        Arc::new(T::default())
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Arc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
 impl<T> Clone for Arc<T> {
     fn clone(&self) -> Arc<T> {
         unsafe {
@@ -96,7 +155,7 @@ impl<T> Drop for Arc<T> {
     }
 }
 
-struct Weak<T> {
+pub struct Weak<T> {
     ptr: NonNull<ArcInner<T>>,
 }
 
@@ -104,6 +163,26 @@ unsafe impl<T: Send + Sync> Sync for Weak<T> {}
 unsafe impl<T: Send + Sync> Send for Weak<T> {}
 
 impl<T> Weak<T> {
+    // 0 once the value is dropped, upgrade then returns None
+    pub fn strong_count(&self) -> usize {
+        unsafe { self.ptr.as_ref().strong.load(Relaxed) }
+    }
+
+    // Weak pointers only, like Arc::weak_count, and 0 once the value is dropped (same as std)
+    // get_mut can't have the weak count locked, it only does that when there is no Weak
+    pub fn weak_count(&self) -> usize {
+        let inner = unsafe { self.ptr.as_ref() };
+        if inner.strong.load(Relaxed) == 0 {
+            return 0;
+        }
+        inner.weak.load(Relaxed) - 1
+    }
+
+    // Same allocation, also true after the value is dropped
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        self.ptr == other.ptr
+    }
+
     pub fn upgrade(&self) -> Option<Arc<T>> {
         let mut strong_count = unsafe { self.ptr.as_ref().strong.load(Relaxed) };
         loop {
@@ -137,6 +216,22 @@ impl<T> Weak<T> {
     }
 }
 
+impl<T> Clone for Weak<T> {
+    fn clone(&self) -> Weak<T> {
+        unsafe {
+            self.ptr.as_ref().weak.fetch_add(1, Relaxed);
+        }
+        Weak { ptr: self.ptr }
+    }
+}
+
+// Value might be already dropped, so never touch it
+impl<T> fmt::Debug for Weak<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("(Weak)")
+    }
+}
+
 impl<T> Drop for Weak<T> {
     fn drop(&mut self) {
         unsafe {
@@ -198,4 +293,69 @@ mod tests {
         assert_eq!(NUM_DROPS.load(Relaxed), 1);
         assert!(z.upgrade().is_none());
     }
+
+    #[test]
+    fn test_counts() {
+        let x = Arc::new(1);
+        let y = x.clone();
+        assert_eq!((Arc::strong_count(&x), Arc::weak_count(&x)), (2, 0));
+        let w = Arc::downgrade(&x);
+        assert_eq!((Arc::strong_count(&y), Arc::weak_count(&y)), (2, 1));
+        assert_eq!(w.strong_count(), 2);
+
+        assert!(Arc::ptr_eq(&x, &y));
+        assert!(!Arc::ptr_eq(&x, &Arc::new(1)));
+
+        let w2 = w.clone();
+        assert_eq!(w.weak_count(), 2);
+        assert!(w.ptr_eq(&w2));
+        assert!(!w.ptr_eq(&Arc::downgrade(&Arc::new(1))));
+
+        drop((x, y));
+        assert_eq!(w.strong_count(), 0);
+        // Nothing to upgrade to anymore, the weak pointers don't count either
+        assert_eq!(w.weak_count(), 0);
+        assert!(w.ptr_eq(&w2));
+    }
+
+    #[test]
+    fn test_get_mut() {
+        let mut x = Arc::new(1);
+        *Arc::get_mut(&mut x).unwrap() += 1;
+        assert_eq!(*x, 2);
+
+        let y = x.clone();
+        assert!(Arc::get_mut(&mut x).is_none());
+        drop(y);
+        assert!(Arc::get_mut(&mut x).is_some());
+
+        // A Weak could upgrade while the &mut is alive
+        let w = Arc::downgrade(&x);
+        assert!(Arc::get_mut(&mut x).is_none());
+        drop(w);
+        assert!(Arc::get_mut(&mut x).is_some());
+        // get_mut left the weak count as it was
+        assert_eq!(Arc::weak_count(&x), 0);
+        let w = Arc::downgrade(&x);
+        assert_eq!(w.upgrade().as_deref(), Some(&2));
+    }
+
+    #[test]
+    fn test_get_mut_with_concurrent_downgrade() {
+        let mut x = Arc::new(0);
+        for _ in 0..1000 {
+            let y = x.clone();
+            let t = std::thread::spawn(move || {
+                // Downgrade through another Arc, then give up the strong reference
+                let w = Arc::downgrade(&y);
+                drop(y);
+                w
+            });
+            // Either the Arc or the Weak made from it is still around until join
+            let got = Arc::get_mut(&mut x).is_some();
+            let w = t.join().unwrap();
+            assert!(!got);
+            assert_eq!(w.strong_count(), 1);
+        }
+    }
 }
@@ -0,0 +1,203 @@
+use std::sync::atomic::AtomicU32;
+use std::time::Duration;
+
+// Only Linux and Android have the futex syscall, other platforms get the emulated version below
+// Both have the same functions, with the same rules
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub use emulated::{wait, wait_timeout, wake_all, wake_one};
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub use linux::{wait, wait_timeout, wake_all, wake_one};
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod linux {
+    use super::*;
+
+    // Linux futex syscall, there is no wrapper in libc so call it directly through syscall()
+    // FUTEX_PRIVATE_FLAG tells the kernel the atomic is only shared between threads of this process
+
+    // Put the thread to sleep only if the atomic still holds the expected value
+    // The check and the sleep are done atomically by the kernel, so a wake() can't be missed in between
+    // It can return spuriously, so always re-check the condition in a loop
+    pub fn wait(a: &AtomicU32, expected: u32) {
+        unsafe {
+            libc::syscall(
+                libc::SYS_futex,
+                a as *const AtomicU32,
+                libc::FUTEX_WAIT | libc::FUTEX_PRIVATE_FLAG,
+                expected,
+                std::ptr::null::<libc::timespec>(),
+            );
+        }
+    }
+
+    // Same as wait() but give up after timeout (relative time)
+    // Return false only when the kernel reports the timeout is reached
+    pub fn wait_timeout(a: &AtomicU32, expected: u32, timeout: Duration) -> bool {
+        let timeout = libc::timespec {
+            tv_sec: timeout.as_secs().min(libc::time_t::MAX as u64) as libc::time_t,
+            tv_nsec: timeout.subsec_nanos() as libc::c_long,
+        };
+        let r = unsafe {
+            libc::syscall(
+                libc::SYS_futex,
+                a as *const AtomicU32,
+                libc::FUTEX_WAIT | libc::FUTEX_PRIVATE_FLAG,
+                expected,
+                &timeout as *const libc::timespec,
+            )
+        };
+        !(r == -1 && std::io::Error::last_os_error().raw_os_error() == Some(libc::ETIMEDOUT))
+    }
+
+    pub fn wake_one(a: &AtomicU32) {
+        unsafe {
+            libc::syscall(
+                libc::SYS_futex,
+                a as *const AtomicU32,
+                libc::FUTEX_WAKE | libc::FUTEX_PRIVATE_FLAG,
+                1,
+            );
+        }
+    }
+
+    pub fn wake_all(a: &AtomicU32) {
+        unsafe {
+            libc::syscall(
+                libc::SYS_futex,
+                a as *const AtomicU32,
+                libc::FUTEX_WAKE | libc::FUTEX_PRIVATE_FLAG,
+                i32::MAX,
+            );
+        }
+    }
+}
+
+// No futex: every atomic is hashed to one of a fixed number of buckets, each a std Mutex + Condvar
+// The waiter checks the value under the bucket lock and the waker takes the same lock before
+// notifying, so like with the kernel a wake can't slip in between the check and the sleep
+// Atomics sharing a bucket wake each other up too, wait can return spuriously anyway
+// Also built for tests on Linux, so it's tested there too
+#[cfg(any(test, not(any(target_os = "linux", target_os = "android"))))]
+mod emulated {
+    use super::*;
+    use std::sync::atomic::Ordering::Relaxed;
+    use std::sync::{Condvar, Mutex, MutexGuard};
+
+    const BUCKETS: usize = 64;
+
+    struct Bucket {
+        lock: Mutex<()>,
+        cond: Condvar,
+    }
+
+    static TABLE: [Bucket; BUCKETS] = [const {
+        Bucket {
+            lock: Mutex::new(()),
+            cond: Condvar::new(),
+        }
+    }; BUCKETS];
+
+    fn bucket(a: &AtomicU32) -> &'static Bucket {
+        // Atomics are 4 bytes aligned, the low bits are always the same
+        &TABLE[(a as *const AtomicU32 as usize / 4) % BUCKETS]
+    }
+
+    // Nothing panics while holding a bucket lock, but don't let a poisoned one stop everyone
+    fn lock(bucket: &Bucket) -> MutexGuard<'_, ()> {
+        bucket.lock.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn wait(a: &AtomicU32, expected: u32) {
+        let bucket = bucket(a);
+        let guard = lock(bucket);
+        if a.load(Relaxed) == expected {
+            drop(bucket.cond.wait(guard));
+        }
+    }
+
+    pub fn wait_timeout(a: &AtomicU32, expected: u32, timeout: Duration) -> bool {
+        let bucket = bucket(a);
+        let guard = lock(bucket);
+        if a.load(Relaxed) != expected {
+            return true;
+        }
+        match bucket.cond.wait_timeout(guard, timeout) {
+            Ok((_, r)) => !r.timed_out(),
+            Err(e) => !e.into_inner().1.timed_out(),
+        }
+    }
+
+    // Waiters on other atomics of the bucket can't be told apart, so wake them all,
+    // otherwise the one woken up could be a waiter of another atomic and ours would keep sleeping
+    pub fn wake_one(a: &AtomicU32) {
+        wake_all(a);
+    }
+
+    pub fn wake_all(a: &AtomicU32) {
+        let bucket = bucket(a);
+        drop(lock(bucket));
+        bucket.cond.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::Ordering::Relaxed;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test() {
+        let a = AtomicU32::new(0);
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(100));
+                a.store(1, Relaxed);
+                wake_one(&a);
+            });
+
+            while a.load(Relaxed) == 0 {
+                wait(&a, 0);
+            }
+        });
+
+        assert_eq!(a.load(Relaxed), 1);
+    }
+
+    #[test]
+    fn test_not_wait_when_value_changed() {
+        // Value is not the expected one, so wait() must return immediately instead of sleeping forever
+        let a = AtomicU32::new(1);
+        wait(&a, 0);
+    }
+
+    #[test]
+    fn test_wait_timeout() {
+        let a = AtomicU32::new(0);
+        assert!(!wait_timeout(&a, 0, Duration::from_millis(10)));
+        // Value mismatch returns immediately, which is not a timeout
+        assert!(wait_timeout(&a, 1, Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn test_emulated() {
+        let a = AtomicU32::new(0);
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(100));
+                a.store(1, Relaxed);
+                emulated::wake_one(&a);
+            });
+
+            while a.load(Relaxed) == 0 {
+                emulated::wait(&a, 0);
+            }
+        });
+        assert_eq!(a.load(Relaxed), 1);
+
+        emulated::wait(&a, 0);
+        assert!(!emulated::wait_timeout(&a, 1, Duration::from_millis(10)));
+        assert!(emulated::wait_timeout(&a, 0, Duration::from_secs(10)));
+    }
+}
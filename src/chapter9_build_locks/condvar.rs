@@ -0,0 +1,249 @@
+use crate::chapter8_os_primitives::futex::{wait, wait_timeout, wake_all, wake_one};
+use crate::chapter9_build_locks::mutex::MutexGuard;
+use std::fmt;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::atomic::{AtomicU32, AtomicUsize};
+use std::time::Duration;
+
+pub struct Condvar {
+    // Every notify bumps the counter, so a waiter can tell if a notification happened
+    // between unlocking the mutex and going to sleep
+    counter: AtomicU32,
+    // Skip the wake syscall when nobody is waiting
+    num_waiters: AtomicUsize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WaitTimeoutResult(bool);
+
+impl WaitTimeoutResult {
+    pub fn timed_out(&self) -> bool {
+        self.0
+    }
+}
+
+impl Condvar {
+    pub const fn new() -> Condvar {
+        Condvar {
+            counter: AtomicU32::new(0),
+            num_waiters: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn notify_one(&self) {
+        if self.num_waiters.load(Relaxed) > 0 {
+            self.counter.fetch_add(1, Relaxed);
+            wake_one(&self.counter);
+        }
+    }
+
+    pub fn notify_all(&self) {
+        if self.num_waiters.load(Relaxed) > 0 {
+            self.counter.fetch_add(1, Relaxed);
+            wake_all(&self.counter);
+        }
+    }
+
+    // Can wake up spuriously, so caller should re-check its condition (or use wait_while)
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        // Register as waiter while still holding the lock
+        // A notifier must take the lock to change the condition, so it can't miss us
+        self.num_waiters.fetch_add(1, Relaxed);
+
+        let counter_value = self.counter.load(Relaxed);
+
+        let mutex = guard.mutex;
+        drop(guard);
+
+        // If notify happened after unlock, counter changed and futex wait returns immediately
+        wait(&self.counter, counter_value);
+
+        self.num_waiters.fetch_sub(1, Relaxed);
+
+        mutex.lock()
+    }
+
+    pub fn wait_timeout<'a, T>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        timeout: Duration,
+    ) -> (MutexGuard<'a, T>, WaitTimeoutResult) {
+        self.num_waiters.fetch_add(1, Relaxed);
+
+        let counter_value = self.counter.load(Relaxed);
+
+        let mutex = guard.mutex;
+        drop(guard);
+
+        let woken = wait_timeout(&self.counter, counter_value, timeout);
+
+        self.num_waiters.fetch_sub(1, Relaxed);
+
+        (mutex.lock(), WaitTimeoutResult(!woken))
+    }
+
+    // Block until condition return false, spurious wakeups are hidden from the caller
+    pub fn wait_while<'a, T, F>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut condition: F,
+    ) -> MutexGuard<'a, T>
+    where
+        F: FnMut(&mut T) -> bool,
+    {
+        while condition(&mut *guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Condvar {
+        Condvar::new()
+    }
+}
+
+impl fmt::Debug for Condvar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Condvar").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chapter9_build_locks::mutex::Mutex;
+    use std::thread;
+    use std::time::Instant;
+
+    #[test]
+    fn test() {
+        let mutex = Mutex::new(0);
+        let condvar = Condvar::new();
+
+        let mut wakeups = 0;
+
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(100));
+                *mutex.lock() = 123;
+                condvar.notify_one();
+            });
+
+            let mut m = mutex.lock();
+            while *m < 100 {
+                m = condvar.wait(m);
+                wakeups += 1;
+            }
+
+            assert_eq!(*m, 123);
+        });
+
+        // Check that the main thread actually did wait (not busy-loop),
+        // while still allowing for a few spurious wake ups
+        assert!(wakeups < 10);
+    }
+
+    #[test]
+    fn test_notify_without_waiters() {
+        let condvar = Condvar::new();
+        condvar.notify_one();
+        condvar.notify_all();
+        // Nobody waits, so the counter is untouched and no syscall is made
+        assert_eq!(condvar.counter.load(Relaxed), 0);
+    }
+
+    #[test]
+    fn test_spurious_wakeup() {
+        let mutex = Mutex::new(false);
+        let condvar = Condvar::new();
+
+        thread::scope(|s| {
+            s.spawn(|| {
+                // Notifications without changing the condition look like spurious wakeups to the waiter
+                for _ in 0..10 {
+                    condvar.notify_all();
+                    thread::sleep(Duration::from_millis(5));
+                }
+                *mutex.lock() = true;
+                condvar.notify_all();
+            });
+
+            let ready = condvar.wait_while(mutex.lock(), |ready| !*ready);
+            assert!(*ready);
+        });
+    }
+
+    #[test]
+    fn test_lost_notification() {
+        // Ping-pong between two threads, each notify happens right after the other side
+        // starts waiting. If any notification is lost, one of them sleeps forever
+        let turn = Mutex::new(0u32);
+        let condvar = Condvar::new();
+        const ROUNDS: u32 = 1000;
+
+        thread::scope(|s| {
+            for me in 0..2 {
+                let turn = &turn;
+                let condvar = &condvar;
+                s.spawn(move || {
+                    for _ in 0..ROUNDS {
+                        let mut t = condvar.wait_while(turn.lock(), |t| *t % 2 != me);
+                        *t += 1;
+                        drop(t);
+                        condvar.notify_all();
+                    }
+                });
+            }
+        });
+
+        assert_eq!(turn.into_inner(), ROUNDS * 2);
+    }
+
+    #[test]
+    fn test_notify_all() {
+        let mutex = Mutex::new(false);
+        let condvar = Condvar::new();
+
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    let started = condvar.wait_while(mutex.lock(), |started| !*started);
+                    assert!(*started);
+                });
+            }
+
+            thread::sleep(Duration::from_millis(50));
+            *mutex.lock() = true;
+            condvar.notify_all();
+        });
+    }
+
+    #[test]
+    fn test_wait_timeout() {
+        let mutex = Mutex::new(());
+        let condvar = Condvar::new();
+
+        let start = Instant::now();
+        let (_guard, result) = condvar.wait_timeout(mutex.lock(), Duration::from_millis(50));
+        assert!(result.timed_out());
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        let mutex = Mutex::new(false);
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(50));
+                *mutex.lock() = true;
+                condvar.notify_one();
+            });
+
+            let mut done = mutex.lock();
+            while !*done {
+                let (guard, result) = condvar.wait_timeout(done, Duration::from_secs(10));
+                assert!(!result.timed_out());
+                done = guard;
+            }
+        });
+    }
+}
@@ -0,0 +1,3 @@
+pub(crate) mod condvar;
+pub(crate) mod mutex;
+pub(crate) mod rwlock;
@@ -0,0 +1,164 @@
+use crate::chapter8_os_primitives::futex::{wait, wake_one};
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+
+const UNLOCKED: u32 = 0;
+const LOCKED: u32 = 1;
+// Locked and there might be other threads sleeping on the futex
+const CONTENDED: u32 = 2;
+
+pub struct Mutex<T> {
+    state: AtomicU32,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(val: T) -> Mutex<T> {
+        Mutex {
+            state: AtomicU32::new(UNLOCKED),
+            data: UnsafeCell::new(val),
+        }
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        // Fast path: uncontended lock never enters the kernel
+        if self
+            .state
+            .compare_exchange(UNLOCKED, LOCKED, Acquire, Relaxed)
+            .is_err()
+        {
+            lock_contended(&self.state);
+        }
+        MutexGuard { mutex: self }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+}
+
+fn lock_contended(state: &AtomicU32) {
+    // Lock is usually held for a very short time, so spin a bit before going to sleep
+    // Only spin while there are no waiters, otherwise it's unfair to the sleeping threads
+    let mut spin_count = 0;
+    while state.load(Relaxed) == LOCKED && spin_count < 100 {
+        spin_count += 1;
+        std::hint::spin_loop();
+    }
+
+    if state
+        .compare_exchange(UNLOCKED, LOCKED, Acquire, Relaxed)
+        .is_ok()
+    {
+        return;
+    }
+
+    // We don't know if there are other waiters, so always mark the state as CONTENDED
+    // That makes the unlocking thread wake one of us up
+    while state.swap(CONTENDED, Acquire) != UNLOCKED {
+        wait(state, CONTENDED);
+    }
+}
+
+impl<T: Default> Default for Mutex<T> {
+    fn default() -> Mutex<T> {
+        Mutex::new(T::default())
+    }
+}
+
+impl<T> fmt::Debug for Mutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Mutex")
+            .field("locked", &(self.state.load(Relaxed) != UNLOCKED))
+            .finish_non_exhaustive()
+    }
+}
+
+pub struct MutexGuard<'a, T> {
+    pub(crate) mutex: &'a Mutex<T>,
+}
+
+unsafe impl<T: Sync> Sync for MutexGuard<'_, T> {}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for MutexGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        // Only pay for the wake syscall when someone might be waiting
+        if self.mutex.state.swap(UNLOCKED, Release) == CONTENDED {
+            wake_one(&self.mutex.state);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test() {
+        let m = Mutex::new(0);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..5000 {
+                        // Non-atomic read-modify-write, any lost update means exclusion is broken
+                        let mut guard = m.lock();
+                        let value = *guard;
+                        std::hint::black_box(&mut *guard);
+                        *guard = value + 1;
+                    }
+                });
+            }
+        });
+
+        assert_eq!(m.into_inner(), 20000);
+    }
+
+    #[test]
+    fn test_state() {
+        let m = Mutex::new(());
+        let guard = m.lock();
+        // Uncontended lock only takes the fast path
+        assert_eq!(m.state.load(Relaxed), LOCKED);
+
+        thread::scope(|s| {
+            s.spawn(|| drop(m.lock()));
+            // Waiter marks the state as contended before going to sleep
+            while m.state.load(Relaxed) != CONTENDED {
+                thread::sleep(Duration::from_millis(10));
+            }
+            drop(guard);
+        });
+
+        assert_eq!(m.state.load(Relaxed), UNLOCKED);
+    }
+}
@@ -0,0 +1,324 @@
+use crate::chapter8_os_primitives::futex::{wait, wake_all, wake_one};
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+
+// Whole lock state in one atomic:
+// - Number of readers times two, plus one if a writer is waiting
+// - u32::MAX if write locked
+// Readers only take the lock when the state is even, so a waiting writer blocks new readers
+// That prevents a steady stream of readers from starving writers
+const WRITE_LOCKED: u32 = u32::MAX;
+
+pub struct RwLock<T> {
+    state: AtomicU32,
+    // Writers sleep on this counter instead of state, so readers coming and going
+    // (which change state) don't wake writers up for nothing
+    writer_wake_counter: AtomicU32,
+    data: UnsafeCell<T>,
+}
+
+// Readers on different threads get &T at the same time, so T also need to be Sync
+unsafe impl<T: Send + Sync> Sync for RwLock<T> {}
+
+impl<T> RwLock<T> {
+    pub const fn new(val: T) -> RwLock<T> {
+        RwLock {
+            state: AtomicU32::new(0),
+            writer_wake_counter: AtomicU32::new(0),
+            data: UnsafeCell::new(val),
+        }
+    }
+
+    pub fn read(&self) -> ReadGuard<'_, T> {
+        let mut s = self.state.load(Relaxed);
+        loop {
+            if s.is_multiple_of(2) {
+                assert!(s < WRITE_LOCKED - 2, "too many readers");
+                match self.state.compare_exchange_weak(s, s + 2, Acquire, Relaxed) {
+                    Ok(_) => return ReadGuard { rwlock: self },
+                    Err(e) => s = e,
+                }
+            }
+            // Write locked or a writer is waiting
+            if !s.is_multiple_of(2) {
+                wait(&self.state, s);
+                s = self.state.load(Relaxed);
+            }
+        }
+    }
+
+    pub fn try_read(&self) -> Option<ReadGuard<'_, T>> {
+        let mut s = self.state.load(Relaxed);
+        // Only retry when other readers change the count, give up as soon as a writer shows up
+        while s.is_multiple_of(2) && s < WRITE_LOCKED - 2 {
+            match self.state.compare_exchange_weak(s, s + 2, Acquire, Relaxed) {
+                Ok(_) => return Some(ReadGuard { rwlock: self }),
+                Err(e) => s = e,
+            }
+        }
+        None
+    }
+
+    pub fn write(&self) -> WriteGuard<'_, T> {
+        let mut s = self.state.load(Relaxed);
+        loop {
+            // Try to lock if unlocked, keep the writer waiting bit out since we are the writer now
+            if s <= 1 {
+                match self
+                    .state
+                    .compare_exchange(s, WRITE_LOCKED, Acquire, Relaxed)
+                {
+                    Ok(_) => return WriteGuard { rwlock: self },
+                    Err(e) => {
+                        s = e;
+                        continue;
+                    }
+                }
+            }
+            // Block new readers by making sure the state is odd
+            if s.is_multiple_of(2) {
+                match self.state.compare_exchange(s, s + 1, Relaxed, Relaxed) {
+                    Ok(_) => {}
+                    Err(e) => {
+                        s = e;
+                        continue;
+                    }
+                }
+            }
+            // Wait if it's still locked
+            let w = self.writer_wake_counter.load(Acquire);
+            s = self.state.load(Relaxed);
+            if s >= 2 {
+                wait(&self.writer_wake_counter, w);
+                s = self.state.load(Relaxed);
+            }
+        }
+    }
+
+    pub fn try_write(&self) -> Option<WriteGuard<'_, T>> {
+        let mut s = self.state.load(Relaxed);
+        while s <= 1 {
+            match self
+                .state
+                .compare_exchange(s, WRITE_LOCKED, Acquire, Relaxed)
+            {
+                Ok(_) => return Some(WriteGuard { rwlock: self }),
+                Err(e) => s = e,
+            }
+        }
+        None
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+}
+
+impl<T: Default> Default for RwLock<T> {
+    fn default() -> RwLock<T> {
+        RwLock::new(T::default())
+    }
+}
+
+impl<T> fmt::Debug for RwLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = self.state.load(Relaxed);
+        f.debug_struct("RwLock")
+            .field("write_locked", &(s == WRITE_LOCKED))
+            .field("readers", &if s == WRITE_LOCKED { 0 } else { s / 2 })
+            .finish_non_exhaustive()
+    }
+}
+
+pub struct ReadGuard<'a, T> {
+    rwlock: &'a RwLock<T>,
+}
+
+impl<T> Deref for ReadGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.rwlock.data.get() }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for ReadGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T> Drop for ReadGuard<'_, T> {
+    fn drop(&mut self) {
+        // Last reader leaves while a writer is waiting (2 + 1 -> 1), wake the writer up
+        if self.rwlock.state.fetch_sub(2, Release) == 3 {
+            self.rwlock.writer_wake_counter.fetch_add(1, Release);
+            wake_one(&self.rwlock.writer_wake_counter);
+        }
+    }
+}
+
+pub struct WriteGuard<'a, T> {
+    rwlock: &'a RwLock<T>,
+}
+
+impl<T> Deref for WriteGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.rwlock.data.get() }
+    }
+}
+
+impl<T> DerefMut for WriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.rwlock.data.get() }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for WriteGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T> Drop for WriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.rwlock.state.store(0, Release);
+        // Wake up one writer first, then all readers
+        // Readers that lose the race to the writer simply go back to sleep
+        self.rwlock.writer_wake_counter.fetch_add(1, Release);
+        wake_one(&self.rwlock.writer_wake_counter);
+        wake_all(&self.rwlock.state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, AtomicUsize};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test() {
+        let nums = RwLock::new(vec![1, 2, 3]);
+        thread::scope(|s| {
+            s.spawn(|| {
+                if let Some(num) = nums.write().first_mut() {
+                    *num += 10;
+                }
+            });
+            s.spawn(|| {
+                let read_guard = nums.read();
+                assert!(read_guard.len() >= 2);
+            });
+            s.spawn(|| {
+                let read_guard = nums.read();
+                assert!(read_guard.len() >= 2);
+            });
+            s.spawn(|| {
+                nums.write().pop();
+            });
+        });
+
+        assert_eq!(nums.into_inner(), vec![11, 2]);
+    }
+
+    #[test]
+    fn test_try_read_and_try_write() {
+        let lock = RwLock::new(0);
+
+        let r1 = lock.read();
+        let r2 = lock.try_read().unwrap();
+        assert_eq!(*r1 + *r2, 0);
+        assert!(lock.try_write().is_none());
+        drop(r1);
+        drop(r2);
+
+        let mut w = lock.try_write().unwrap();
+        *w += 1;
+        assert!(lock.try_read().is_none());
+        assert!(lock.try_write().is_none());
+        drop(w);
+
+        assert_eq!(*lock.try_read().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_waiting_writer_blocks_new_readers() {
+        let lock = RwLock::new(0);
+        let reader = lock.read();
+
+        thread::scope(|s| {
+            s.spawn(|| *lock.write() += 1);
+
+            // Wait until the writer marks itself as waiting
+            while lock.state.load(Relaxed).is_multiple_of(2) {
+                thread::sleep(Duration::from_millis(1));
+            }
+            assert!(lock.try_read().is_none());
+
+            drop(reader);
+        });
+
+        assert_eq!(*lock.read(), 1);
+    }
+
+    #[test]
+    fn test_writer_starvation() {
+        // Readers keep overlapping each other so the lock is never free of readers
+        // A reader-preferring lock would make the writer wait until every reader stops
+        let lock = RwLock::new(0u32);
+        let stop = AtomicBool::new(false);
+        let reads = AtomicUsize::new(0);
+        const WRITES: u32 = 20;
+
+        let (max_wait, behind_readers) = thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| {
+                    while !stop.load(Relaxed) {
+                        let guard = lock.read();
+                        thread::sleep(Duration::from_millis(1));
+                        std::hint::black_box(*guard);
+                        reads.fetch_add(1, Relaxed);
+                    }
+                });
+            }
+
+            let writer = s.spawn(|| {
+                let mut max_wait = Duration::ZERO;
+                // Writes that started while readers were holding the lock
+                let mut behind_readers = 0;
+                for _ in 0..WRITES {
+                    if lock.state.load(Relaxed) >= 2 {
+                        behind_readers += 1;
+                    }
+                    let start = Instant::now();
+                    let mut guard = lock.write();
+                    max_wait = max_wait.max(start.elapsed());
+                    *guard += 1;
+                    drop(guard);
+                    thread::sleep(Duration::from_millis(2));
+                }
+                (max_wait, behind_readers)
+            });
+
+            let result = writer.join().unwrap();
+            stop.store(true, Relaxed);
+            result
+        });
+
+        // Readers really were in the way, the writer had to get past them
+        assert!(behind_readers > 0);
+        assert!(reads.load(Relaxed) > 0);
+        assert_eq!(lock.into_inner(), WRITES);
+        // Writer only has to wait for the readers already inside to leave
+        assert!(max_wait < Duration::from_millis(500));
+    }
+}
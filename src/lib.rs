@@ -4,3 +4,8 @@ mod chapter3_memory_ordering;
 mod chapter4_build_spin_lock;
 mod chapter5_build_channels;
 mod chapter6_build_arc;
+mod chapter8_os_primitives;
+mod chapter9_build_locks;
+
+pub mod channel;
+pub mod sync;
@@ -0,0 +1,37 @@
+// Public surface of the synchronization primitives built through the chapters
+// Chapters stay as they are for learning, this module only re-exports the usable versions
+
+pub use crate::chapter4_build_spin_lock::clh_version::{ClhLock, Guard as ClhLockGuard};
+pub use crate::chapter4_build_spin_lock::mcs_version::{Guard as McsLockGuard, McsLock};
+pub use crate::chapter4_build_spin_lock::safe_version::{Guard as SpinLockGuard, SpinLock};
+pub use crate::chapter4_build_spin_lock::ticket_version::{Guard as TicketLockGuard, TicketLock};
+pub use crate::chapter4_build_spin_lock::ttas_version::{
+    Backoff, Guard as TtasSpinLockGuard, SpinLock as TtasSpinLock,
+};
+pub use crate::chapter6_build_arc::weak_pointer::{Arc, Weak};
+pub use crate::chapter9_build_locks::condvar::{Condvar, WaitTimeoutResult};
+pub use crate::chapter9_build_locks::mutex::{Mutex, MutexGuard};
+pub use crate::chapter9_build_locks::rwlock::{ReadGuard, RwLock, WriteGuard};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_share_between_spawned_threads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<SpinLock<Vec<i32>>>();
+        assert_send_sync::<Arc<SpinLock<Vec<i32>>>>();
+        assert_send_sync::<Weak<SpinLock<Vec<i32>>>>();
+        assert_send_sync::<Mutex<Vec<i32>>>();
+
+        let nums = Arc::new(SpinLock::<Vec<i32>>::default());
+        let weak = Arc::downgrade(&nums);
+        let t = thread::spawn(move || weak.upgrade().unwrap().lock().push(1));
+        t.join().unwrap();
+
+        assert_eq!(*nums.lock(), [1]);
+        assert_eq!(format!("{nums:?}"), "SpinLock { locked: false, .. }");
+    }
+}